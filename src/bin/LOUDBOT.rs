@@ -3,20 +3,24 @@
 //! environment, sourcing a `.env` file if one exists.
 #![allow(non_snake_case)]
 use axum::{
-    extract::Extension,
-    http::StatusCode,
+    body::Bytes,
+    extract::{Extension, Query},
+    http::{HeaderMap, StatusCode},
+    response::Redirect,
     routing::{get, post},
     Json, Router,
 };
 use dotenv::dotenv;
-use serde::Deserialize;
-use slack_api as slack;
+use tokio::signal::unix::{signal, SignalKind};
 
 use std::collections::HashMap;
 use std::net::SocketAddr;
 use std::sync::Arc;
 
-use LOUDCRAB::{Loudbot, LoudbotSlack};
+use LOUDCRAB::{
+    ChannelSettings, Corpus, Curation, EventQueue, IncomingEvent, Keys, Loudbot, LoudbotSlack, LoudbotSocket,
+    MemoryStore, OAuthSettings, QueueStats, RateLimits, RedisStore, SeededDice, ShoutStore,
+};
 
 /// Respond to ping. Useful for monitoring.
 async fn ping(Extension(loudie): Extension<Arc<Loudbot>>) -> String {
@@ -27,31 +31,92 @@ async fn ping(Extension(loudie): Extension<Arc<Loudbot>>) -> String {
     }
 }
 
-/// The parts of an incoming Slack webhook poast that we care about.
-#[derive(Deserialize, Debug)]
-struct IncomingEvent {
-    /// Verification token, which must match what we expect.
-    token: String,
-    /// Type of the incoming message event.
-    #[serde(rename = "type")]
-    message_type: Option<String>,
-    /// Full event payload.
-    event: Option<slack::Message>,
-    /// The remainder of the envelope, which is only needed sometimes.
-    #[serde(flatten)]
-    rest: HashMap<String, serde_json::Value>,
+/// Report on the event queue. Useful for watching backpressure.
+async fn queue_stats(Extension(queue): Extension<Arc<EventQueue>>) -> Json<QueueStats> {
+    Json(queue.stats())
+}
+
+/// Send someone off to Slack to install us in their workspace.
+async fn install(Extension(loudie): Extension<Arc<LoudbotSlack>>) -> Result<Redirect, (StatusCode, String)> {
+    match loudie.begin_install().await {
+        Ok(url) => Ok(Redirect::to(&url)),
+        Err(e) => {
+            log::warn!("unable to start an install: {:?}", e);
+            Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "LOUDBOT CANNOT BE INSTALLED RIGHT NOW".to_string(),
+            ))
+        }
+    }
+}
+
+/// Slack sends people back here once they've approved installing us.
+async fn installed(
+    Extension(loudie): Extension<Arc<LoudbotSlack>>,
+    Query(params): Query<HashMap<String, String>>,
+) -> (StatusCode, String) {
+    let (code, state) = match (params.get("code"), params.get("state")) {
+        (Some(code), Some(state)) => (code, state),
+        _ => {
+            let reason = params.get("error").map(|e| e.as_str()).unwrap_or("no code");
+            log::info!("install did not happen: {reason}");
+            return (StatusCode::BAD_REQUEST, "LOUDBOT WAS NOT INSTALLED".to_string());
+        }
+    };
+    match loudie.finish_install(code, state).await {
+        Ok(team) => (
+            StatusCode::OK,
+            format!("LOUDBOT IS NOW SHOUTING IN {}", team.to_uppercase()),
+        ),
+        Err(e) => {
+            log::warn!("unable to finish an install: {:?}", e);
+            (StatusCode::BAD_REQUEST, "LOUDBOT WAS NOT INSTALLED".to_string())
+        }
+    }
+}
+
+/// Fetch a header value as a string, if it's present and legible.
+fn header<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|v| v.to_str().ok())
 }
 
 /// Handle an incoming post from Slack.
 async fn incoming(
-    Json(incoming): Json<IncomingEvent>,
     Extension(loudie): Extension<Arc<LoudbotSlack>>,
+    Extension(queue): Extension<Arc<EventQueue>>,
+    headers: HeaderMap,
+    body: Bytes,
 ) -> (StatusCode, String) {
-    // if the token doesn't match, yell and bail
-    if incoming.token != loudie.verification {
-        return (StatusCode::BAD_REQUEST, "invalid payload".to_string());
+    // We need the raw body to check the signature, so we parse it ourselves, and only
+    // once we know it's from Slack. The legacy verification token lives in the body, so
+    // that's the one thing we look for first.
+    let token = serde_json::from_slice::<serde_json::Value>(&body)
+        .ok()
+        .and_then(|body| body["token"].as_str().map(|token| token.to_string()));
+
+    // if the request isn't from Slack, yell and bail
+    match loudie.verify_request(
+        header(&headers, "X-Slack-Request-Timestamp"),
+        header(&headers, "X-Slack-Signature"),
+        &body,
+        token.as_deref(),
+    ) {
+        Ok(true) => {}
+        Ok(false) => return (StatusCode::UNAUTHORIZED, "invalid signature".to_string()),
+        Err(e) => {
+            log::warn!("error verifying request: {:?}", e);
+            return (StatusCode::UNAUTHORIZED, "invalid signature".to_string());
+        }
     }
 
+    let mut incoming: IncomingEvent = match serde_json::from_slice(&body) {
+        Ok(v) => v,
+        Err(e) => {
+            log::info!("unparseable incoming post: {:?}", e);
+            return (StatusCode::BAD_REQUEST, "invalid payload".to_string());
+        }
+    };
+
     // This clone is to avoid a partial move of incoming so we can debug print later. I hate it.
     let res = if let Some(v) = incoming.message_type.clone() {
         if v == "url_verification" {
@@ -61,16 +126,17 @@ async fn incoming(
             });
             (StatusCode::OK, retort.to_string())
         } else if v == "event_callback" {
-            if let Some(event) = incoming.event {
-                match loudie.handle_message(event).await {
-                    Ok(_) => log::debug!("handled callback successfully"),
-                    Err(e) => log::warn!("error handling callback: {:?}", e),
+            incoming.retry_num = header(&headers, "X-Slack-Retry-Num").and_then(|v| v.parse().ok());
+            incoming.retry_reason = header(&headers, "X-Slack-Retry-Reason").map(|v| v.to_string());
+            // Hand the event to the workers and respond right away. If we're too backed
+            // up to take it, we'd rather Slack try again later than wait on us.
+            match queue.enqueue(incoming) {
+                Ok(_) => (StatusCode::OK, "OK".to_string()),
+                Err(e) => {
+                    log::warn!("turning away event: {:?}", e);
+                    (StatusCode::SERVICE_UNAVAILABLE, "busy".to_string())
                 }
-            } else {
-                log::warn!("incoming post did not have a valid structure {:?}", incoming);
             }
-            // respond with 200 OK no matter what (we should do this immediately, but we can't)
-            (StatusCode::OK, "OK".to_string())
         } else {
             log::info!("unhandled type: {}", v);
             (StatusCode::OK, "OK".to_string())
@@ -88,13 +154,49 @@ async fn main() {
     dotenv().ok();
     simple_logger::init_with_env().ok();
 
-    let slack_token =
-        std::env::var("SLACK_TOKEN").expect("You must provide a valid slack api token in the env var SLACK_TOKEN.");
-    let verification = std::env::var("VERIFICATION_TOKEN")
-        .expect("You must provide your slack verification token in the env var VERIFICATION_TOKEN.");
+    // With oauth settings, workspaces can install us and hand us their own tokens, so we
+    // don't need one of our own.
+    let oauth = match (std::env::var("SLACK_CLIENT_ID"), std::env::var("SLACK_CLIENT_SECRET")) {
+        (Ok(client_id), Ok(client_secret)) => Some(OAuthSettings {
+            client_id,
+            client_secret,
+            redirect_uri: std::env::var("SLACK_REDIRECT_URI").ok(),
+        }),
+        _ => None,
+    };
+    let slack_token = std::env::var("SLACK_TOKEN").ok();
+    if slack_token.is_none() && oauth.is_none() {
+        panic!("You must provide a valid slack api token in the env var SLACK_TOKEN.");
+    }
+    // If we have an app token, we receive events over a Socket Mode websocket instead of
+    // by webhook, and we don't need to check request signatures.
+    let app_token = std::env::var("SLACK_APP_TOKEN").ok();
+    let signing_secret = std::env::var("SLACK_SIGNING_SECRET").ok();
+    let verification = std::env::var("VERIFICATION_TOKEN").ok();
+    if app_token.is_none() && signing_secret.is_none() && verification.is_none() {
+        panic!("You must provide your slack signing secret in the env var SLACK_SIGNING_SECRET.");
+    }
+    if app_token.is_none() && signing_secret.is_none() {
+        log::warn!("No SLACK_SIGNING_SECRET set; falling back to the deprecated VERIFICATION_TOKEN.");
+    }
 
-    let redis_uri = std::env::var("REDIS_URL").unwrap_or_else(|_| "redis://127.0.0.1:6379".to_string());
-    log::info!("BRAIN @ {}", redis_uri);
+    let store: Arc<dyn ShoutStore> = match std::env::var("STORE").as_deref() {
+        Ok("memory") => {
+            log::info!("BRAIN @ MEMORY. LOUDBOT WILL FORGET EVERYTHING WHEN IT STOPS.");
+            Arc::new(MemoryStore::new())
+        }
+        #[cfg(feature = "sqlite")]
+        Ok("sqlite") => {
+            let path = std::env::var("SQLITE_PATH").unwrap_or_else(|_| "LOUDBOT.sqlite".to_string());
+            log::info!("BRAIN @ {}", path);
+            Arc::new(LOUDCRAB::SqliteStore::open(&path).unwrap()) // intentional
+        }
+        _ => {
+            let redis_uri = std::env::var("REDIS_URL").unwrap_or_else(|_| "redis://127.0.0.1:6379".to_string());
+            log::info!("BRAIN @ {}", redis_uri);
+            Arc::new(RedisStore::new(&redis_uri).unwrap()) // intentional
+        }
+    };
     let host = std::env::var("HOST").unwrap_or_else(|_| "127.0.0.1".to_string());
     let port = std::env::var("PORT").unwrap_or_else(|_| "5000".to_string());
     let prefix = std::env::var("ROUTE_PREFIX").unwrap_or_else(|_| "".to_string());
@@ -108,19 +210,173 @@ async fn main() {
         },
         Err(_) => 2,
     };
+    let yell_chance: u8 = std::env::var("YELL_CHANCE")
+        .ok()
+        .and_then(|v| v.parse::<u8>().ok())
+        .map(|x| std::cmp::min(x, 100))
+        .unwrap_or(100);
+    let dice_seed: Option<u64> = std::env::var("DICE_SEED").ok().and_then(|v| v.parse().ok());
+    let workers: usize = std::env::var("WORKERS").ok().and_then(|v| v.parse().ok()).unwrap_or(4);
+    // Seeded dice are only repeatable if events take their rolls in the order they
+    // arrived, so a seed means one worker handling one event at a time.
+    let workers = if dice_seed.is_some() { 1 } else { workers };
+    let queue_depth: usize = std::env::var("QUEUE_DEPTH")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(100);
+
+    let reaction_chance: u8 = std::env::var("REACTION_CHANCE")
+        .ok()
+        .and_then(|v| v.parse::<u8>().ok())
+        .map(|x| std::cmp::min(x, 100))
+        .unwrap_or(10);
+    let reactions: Vec<String> = match std::env::var("REACTIONS") {
+        Ok(v) => v
+            .split(',')
+            .map(|x| x.trim().trim_matches(':').to_string())
+            .filter(|x| !x.is_empty())
+            .collect(),
+        Err(_) => LOUDCRAB::loudbot::DEFAULT_REACTIONS
+            .iter()
+            .map(|x| x.to_string())
+            .collect(),
+    };
+
+    let defaults = Curation::default();
+    let curation = Curation {
+        forget: std::env::var("FORGET_EMOJI").unwrap_or(defaults.forget),
+        forget_votes: std::env::var("FORGET_VOTES")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(defaults.forget_votes),
+        favorite: std::env::var("FAVORITE_EMOJI").unwrap_or(defaults.favorite),
+    };
 
-    let loudie = Loudbot::new(redis_uri, malc_chance).unwrap(); // intentional
-    let face = LoudbotSlack::new(slack_token, verification, loudie);
+    let limits = match std::env::var("RATE_LIMITS") {
+        Ok(v) => RateLimits::parse(&v).unwrap_or_else(|e| {
+            log::warn!("Failed to parse RATE_LIMITS; not limiting anything; {:?}", e);
+            RateLimits::default()
+        }),
+        Err(_) => RateLimits::default(),
+    };
+
+    let loudie = Loudbot::with_store(store, malc_chance);
+    // Bad triggers are a mistake worth stopping for, not one to discover by shouting.
+    let loudie = match std::env::var("TRIGGERS_FILE") {
+        Ok(path) => match loudie.with_trigger_file(&path) {
+            Ok(loudie) => {
+                log::info!("LOUDBOT LOADED ITS TRIGGERS FROM {}", path);
+                loudie
+            }
+            Err(e) => panic!("Unable to load triggers: {:#}", e),
+        },
+        Err(_) => loudie,
+    };
+    let loudie = loudie
+        .with_keys(Keys::from_env())
+        .with_corpus(match std::env::var("TEAM_YELLS").as_deref() {
+            Ok("separate") => Corpus::PerTeam,
+            Ok("shared") => Corpus::Shared,
+            _ => Corpus::Global,
+        })
+        .with_reactions(reactions, reaction_chance)
+        .with_curation(curation)
+        .with_rate_limits(limits)
+        .with_yell_chance(yell_chance)
+        .with_channel_defaults(match std::env::var("CHANNELS").as_deref() {
+            Ok("allowlist") => ChannelSettings::allowlist(),
+            _ => ChannelSettings::default(),
+        })
+        .with_snooze_memory(!matches!(
+            std::env::var("SNOOZE_REMEMBERS").as_deref(),
+            Ok("false") | Ok("0")
+        ))
+        .with_admins(
+            std::env::var("ADMINS")
+                .unwrap_or_default()
+                .split(',')
+                .map(|x| x.trim().to_string())
+                .filter(|x| !x.is_empty())
+                .collect(),
+        )
+        .with_anonymous_attribution(matches!(
+            std::env::var("ANONYMOUS_WHO").as_deref(),
+            Ok("true") | Ok("1")
+        ));
+    // A fixed seed makes LOUDBOT's choices repeatable, for chasing down a bug report.
+    let loudie = match dice_seed {
+        Some(seed) => {
+            log::info!("LOUDBOT IS ROLLING DICE SEEDED WITH {seed}");
+            loudie.with_dice(Arc::new(SeededDice::new(seed)))
+        }
+        None => loudie,
+    };
+    // Clones share their triggers, so reloading this one reloads the face's too.
+    let reloader = loudie.clone();
+    tokio::spawn(async move {
+        let mut hangups = match signal(SignalKind::hangup()) {
+            Ok(hangups) => hangups,
+            Err(e) => {
+                log::warn!(
+                    "unable to listen for SIGHUP; reload with the RELOAD command instead; {:?}",
+                    e
+                );
+                return;
+            }
+        };
+        while hangups.recv().await.is_some() {
+            if let Err(e) = reloader.reload_triggers() {
+                log::warn!("kept the old triggers; the new ones are broken: {:#}", e);
+            }
+        }
+    });
+    let mut face = LoudbotSlack::new(slack_token, signing_secret, verification, loudie);
+    let installable = oauth.is_some();
+    if let Some(oauth) = oauth {
+        face = face.with_oauth(oauth);
+    }
     let _ = face.maybe_toast().await; // ignoring errors
+    if let Ok(pattern) = std::env::var("CUSTOM_REACTIONS") {
+        match regex::Regex::new(&pattern) {
+            Ok(pattern) => match face.discover_emoji(&pattern).await {
+                Ok(count) => log::info!("found {count} custom emoji to react with"),
+                Err(e) => log::warn!("unable to list custom emoji: {:?}", e),
+            },
+            Err(e) => log::warn!("CUSTOM_REACTIONS is not a valid regex; {:?}", e),
+        }
+    }
+    let face = Arc::new(face);
+    let queue = Arc::new(EventQueue::new(face.clone(), workers, queue_depth));
 
-    let app = Router::new()
+    let mut app = Router::new()
         .route(&format!("{}/monitor/ping", prefix), get(ping))
-        .route(&format!("{}/incoming", prefix), post(incoming))
-        .layer(Extension(Arc::new(face)));
+        .route(&format!("{}/monitor/queue", prefix), get(queue_stats));
+    if let Some(app_token) = app_token {
+        let socket = LoudbotSocket::new(app_token, queue.clone());
+        tokio::spawn(async move { socket.run().await });
+        log::info!("LOUDBOT IS USING SOCKET MODE");
+    } else {
+        app = app.route(&format!("{}/incoming", prefix), post(incoming));
+    }
+    if installable {
+        app = app
+            .route(&format!("{}/oauth/install", prefix), get(install))
+            .route(&format!("{}/oauth/redirect", prefix), get(installed));
+    }
+    let app = app.layer(Extension(face)).layer(Extension(queue.clone()));
 
     let addr = format!("{}:{}", host, port);
     log::info!("LOUDBOT TUNED FOR SHOUTS COMING IN ON {}", &addr);
 
     let addr: SocketAddr = addr.parse().unwrap();
-    axum::Server::bind(&addr).serve(app.into_make_service()).await.unwrap();
+    axum::Server::bind(&addr)
+        .serve(app.into_make_service())
+        .with_graceful_shutdown(async {
+            let _ = tokio::signal::ctrl_c().await;
+        })
+        .await
+        .unwrap();
+
+    log::info!("LOUDBOT IS FINISHING ITS LAST FEW SHOUTS");
+    queue.drain().await;
 }
@@ -7,13 +7,33 @@ use std::fs::File;
 use std::io::{BufRead, BufReader};
 use std::path::Path;
 
+use LOUDCRAB::{shout_id, Keys};
+
 type RCount = std::result::Result<u32, redis::RedisError>;
 
+/// Every workspace with a pool of its own, found by scanning for their yell sets.
+fn teams(db: &mut redis::Connection, keys: &Keys) -> Result<Vec<String>, redis::RedisError> {
+    let pattern = keys.team_yells("*");
+    let (before, after) = pattern.split_once('*').unwrap_or((&pattern, ""));
+    let found: Vec<String> = db.scan_match(&pattern)?.collect();
+    Ok(found
+        .iter()
+        .filter_map(|key| key.strip_prefix(before)?.strip_suffix(after))
+        .map(|team| team.to_string())
+        .collect())
+}
+
+/// Remove every line in the file from the global pool and from each workspace's own,
+/// along with its provenance records and any favor it had.
 fn prune_from_file(
     db: &mut redis::Connection,
     filename: impl AsRef<Path> + std::fmt::Debug + Copy,
-    key: &str,
+    keys: &Keys,
+    teams: &[String],
 ) -> Result<u32, redis::RedisError> {
+    let scopes: Vec<Option<&str>> = std::iter::once(None)
+        .chain(teams.iter().map(|team| Some(team.as_str())))
+        .collect();
     let mut count: u32 = 0;
     let file = File::open(filename).expect("no such file");
     let reader = BufReader::new(file);
@@ -22,11 +42,21 @@ fn prune_from_file(
         if text.len() < 2 {
             continue;
         }
-        let res: RCount = db.srem(key, text);
-        match res {
-            Err(e) => println!("{:?}", e),
-            Ok(i) => {
-                count += i;
+        let id = shout_id(&text);
+        for scope in &scopes {
+            let (yells, favorites) = match scope {
+                Some(team) => (keys.team_yells(team), keys.team_favorites(team)),
+                None => (keys.yells(), keys.favorites()),
+            };
+            let res: RCount = db.srem(&yells, &text);
+            match res {
+                Err(e) => println!("{:?}", e),
+                Ok(i) => {
+                    count += i;
+                    // Forget where it came from, and that anybody liked it, too.
+                    let _: RCount = db.zrem(&favorites, &text);
+                    let _: RCount = db.del(&[keys.shout(*scope, &id), keys.shout_yelled(*scope, &id)]);
+                }
             }
         }
     }
@@ -41,20 +71,17 @@ fn main() -> Result<()> {
         Ok(v) => v,
         Err(_) => "redis://127.0.0.1:6379".to_string(),
     };
-    let redis_prefix = match env::var("REDIS_PREFIX") {
-        Ok(v) => v,
-        Err(_) => "LB".to_string(),
-    };
+    let keys = Keys::from_env();
     let client = redis::Client::open(redis_uri.as_ref())
         .with_context(|| format!("Unable to create redis client @ {}", redis_uri))?;
     let mut rcon = client
         .get_connection()
         .with_context(|| format!("Unable to connect to redis @ {}", redis_uri))?;
 
-    let yellkey = format!("{}:YELLS", redis_prefix);
+    let teams = teams(&mut rcon, &keys).with_context(|| "Looking for workspace pools failed.")?;
 
     for f in std::env::args().skip(1) {
-        prune_from_file(&mut rcon, &f, &yellkey).with_context(|| "Trying to write to redis failed utterly.")?;
+        prune_from_file(&mut rcon, &f, &keys, &teams).with_context(|| "Trying to write to redis failed utterly.")?;
     }
 
     Ok(())
@@ -3,6 +3,9 @@
 //! adds shouts from files listed as arguments.
 //! Example usage: `SEED SEEDS custom.txt`
 //! All seed files must be newline-delimited text files.
+//!
+//! With the `sqlite` feature, `SEED --migrate LOUDBOT.sqlite` instead copies everything
+//! under the redis prefix into a fresh SQLite database for LOUDBOT to use.
 use anyhow::{Context, Result};
 use dotenv::dotenv;
 use regex::Regex;
@@ -44,6 +47,55 @@ fn seed_from_file(
     Ok(count)
 }
 
+/// Copy every key under the prefix from redis into another shout store, along with
+/// its expiry time. Weights are added to, so this is meant for a fresh store.
+#[cfg(feature = "sqlite")]
+async fn migrate(db: &mut redis::Connection, keys: &LOUDCRAB::Keys, store: &dyn LOUDCRAB::ShoutStore) -> Result<u32> {
+    use redis::Commands;
+
+    let found: Vec<String> = db.scan_match(keys.pattern())?.collect();
+    let mut count = 0;
+    for key in found {
+        let kind: String = redis::cmd("TYPE").arg(&key).query(db)?;
+        match kind.as_str() {
+            "set" => {
+                let members: Vec<String> = db.smembers(&key)?;
+                for member in members {
+                    store.remember(&key, &member).await?;
+                }
+            }
+            "string" => {
+                let value: String = db.get(&key)?;
+                store.set(&key, &value, None).await?;
+            }
+            "zset" => {
+                let weights: Vec<(String, f64)> = db.zrange_withscores(&key, 0, -1)?;
+                for (member, weight) in weights {
+                    store.weigh(&key, &member, weight).await?;
+                }
+            }
+            "list" => {
+                // Logs are newest first, so we push the oldest first.
+                let entries: Vec<String> = db.lrange(&key, 0, -1)?;
+                for entry in entries.iter().rev() {
+                    store.log(&key, entry, entries.len()).await?;
+                }
+            }
+            other => {
+                println!("Skipping {key}; can't copy a {other}.");
+                continue;
+            }
+        }
+        let ttl: i64 = db.ttl(&key)?;
+        if ttl > 0 {
+            store.expire(&key, std::time::Duration::from_secs(ttl as u64)).await?;
+        }
+        count += 1;
+    }
+    println!("Copied {count} keys.");
+    Ok(count)
+}
+
 fn main() -> Result<()> {
     dotenv().ok();
 
@@ -51,21 +103,32 @@ fn main() -> Result<()> {
         Ok(v) => v,
         Err(_) => "redis://127.0.0.1:6379".to_string(),
     };
-    let redis_prefix = match env::var("REDIS_PREFIX") {
-        Ok(v) => v,
-        Err(_) => "LB".to_string(),
-    };
+    let keys = LOUDCRAB::Keys::from_env();
     let client = redis::Client::open(redis_uri.as_ref())
         .with_context(|| format!("Unable to create redis client @ {}", redis_uri))?;
     let mut rcon = client
         .get_connection()
         .with_context(|| format!("Unable to connect to redis @ {}", redis_uri))?;
 
+    let mut args = std::env::args().skip(1).peekable();
+    if args.peek().map(|arg| arg == "--migrate").unwrap_or(false) {
+        let path = args.nth(1).unwrap_or_else(|| "LOUDBOT.sqlite".to_string());
+        #[cfg(feature = "sqlite")]
+        {
+            println!("Copying {} from redis @ {redis_uri} to {path}", keys.pattern());
+            let store = LOUDCRAB::SqliteStore::open(&path)?;
+            tokio::runtime::Runtime::new()?.block_on(migrate(&mut rcon, &keys, &store))?;
+            return Ok(());
+        }
+        #[cfg(not(feature = "sqlite"))]
+        anyhow::bail!("Migrating to {path} needs SEED built with the sqlite feature.");
+    }
+
     println!("Saving seed data to redis @ {redis_uri}");
 
-    let yellkey = format!("{}:YELLS", redis_prefix);
+    let yellkey = keys.yells();
 
-    for f in std::env::args().skip(1) {
+    for f in args {
         seed_from_file(&mut rcon, &f, &yellkey, false)?;
     }
 
@@ -0,0 +1,169 @@
+//! WHERE LOUDBOT MAY YELL. Each channel can have its own settings, kept in the store
+//! and changed with the `CHANNEL` command. Channels without settings of their own get
+//! LOUDBOT's defaults, which decide whether channels are allowed until denied or denied
+//! until allowed.
+use serde::{Deserialize, Serialize};
+
+/// How LOUDBOT behaves in one channel.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChannelSettings {
+    /// Whether LOUDBOT listens to shouts here at all. Commands work either way.
+    pub enabled: bool,
+    /// Whether shouts heard here are remembered.
+    pub remember: bool,
+    /// Whether the easter egg triggers fire here.
+    pub triggers: bool,
+    /// The percentage chance Malcolm Tucker appears here, if not the usual one.
+    pub malcolm: Option<u8>,
+    /// The percentage chance we yell back at a shout here, if not the usual one.
+    pub yell_chance: Option<u8>,
+    /// Reply only in threads, to keep the channel itself quiet.
+    pub threads_only: bool,
+}
+
+impl Default for ChannelSettings {
+    fn default() -> Self {
+        ChannelSettings {
+            enabled: true,
+            remember: true,
+            triggers: true,
+            malcolm: None,
+            yell_chance: None,
+            threads_only: false,
+        }
+    }
+}
+
+impl ChannelSettings {
+    /// Defaults for a LOUDBOT that stays out of channels until it's let in.
+    pub fn allowlist() -> Self {
+        ChannelSettings {
+            enabled: false,
+            ..ChannelSettings::default()
+        }
+    }
+
+    pub fn apply(&mut self, change: ChannelChange) {
+        match change {
+            ChannelChange::Enabled(on) => self.enabled = on,
+            ChannelChange::Remember(on) => self.remember = on,
+            ChannelChange::Triggers(on) => self.triggers = on,
+            ChannelChange::Malcolm(chance) => self.malcolm = chance,
+            ChannelChange::YellChance(chance) => self.yell_chance = chance,
+            ChannelChange::ThreadsOnly(on) => self.threads_only = on,
+        }
+    }
+
+    /// The settings, in LOUDBOT's own words.
+    pub fn describe(&self) -> String {
+        let onoff = |on: bool| if on { "ON" } else { "OFF" };
+        let chance = |chance: Option<u8>| match chance {
+            Some(chance) => format!("{chance}%"),
+            None => "THE USUAL".to_string(),
+        };
+        format!(
+            "IN HERE I AM {}. REMEMBERING SHOUTS: {}. YELLING BACK: {}. TRIGGERS: {}. MALCOLM TUCKER: {}. THREADS ONLY: {}.",
+            onoff(self.enabled),
+            onoff(self.remember),
+            chance(self.yell_chance),
+            onoff(self.triggers),
+            chance(self.malcolm),
+            onoff(self.threads_only)
+        )
+    }
+}
+
+/// One change to a channel's settings, as asked for with the `CHANNEL` command.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChannelChange {
+    Enabled(bool),
+    Remember(bool),
+    Triggers(bool),
+    /// A new Malcolm chance, or None to go back to the usual one.
+    Malcolm(Option<u8>),
+    /// A new chance of yelling back, or None to go back to the usual one.
+    YellChance(Option<u8>),
+    ThreadsOnly(bool),
+}
+
+impl ChannelChange {
+    /// Read a change from whatever followed the command: `ON`, `OFF`, `REMEMBER OFF`,
+    /// `TRIGGERS ON`, `MALCOLM 10`, `MALCOLM DEFAULT`, `YELL 30`, `THREADS ON`, and so on.
+    pub fn parse(text: &str) -> Option<ChannelChange> {
+        let words: Vec<String> = text.split_whitespace().map(|w| w.to_uppercase()).collect();
+        let words: Vec<&str> = words.iter().map(|w| w.as_str()).collect();
+        match words[..] {
+            [on] => switch(on).map(ChannelChange::Enabled),
+            ["REMEMBER", on] => switch(on).map(ChannelChange::Remember),
+            ["TRIGGERS", on] => switch(on).map(ChannelChange::Triggers),
+            ["THREADS", on] => switch(on).map(ChannelChange::ThreadsOnly),
+            ["MALCOLM", chance] => percentage(chance).map(ChannelChange::Malcolm),
+            ["YELL", chance] => percentage(chance).map(ChannelChange::YellChance),
+            _ => None,
+        }
+    }
+}
+
+/// A percentage chance, or None for `DEFAULT`.
+fn percentage(word: &str) -> Option<Option<u8>> {
+    if word == "DEFAULT" {
+        return Some(None);
+    }
+    let chance: u8 = word.trim_end_matches('%').parse().ok()?;
+    Some(Some(std::cmp::min(chance, 100)))
+}
+
+fn switch(word: &str) -> Option<bool> {
+    match word {
+        "ON" | "YES" | "ENABLE" => Some(true),
+        "OFF" | "NO" | "DISABLE" => Some(false),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn changes_parse() {
+        assert_eq!(ChannelChange::parse("off"), Some(ChannelChange::Enabled(false)));
+        assert_eq!(
+            ChannelChange::parse("REMEMBER OFF"),
+            Some(ChannelChange::Remember(false))
+        );
+        assert_eq!(ChannelChange::parse("triggers on"), Some(ChannelChange::Triggers(true)));
+        assert_eq!(
+            ChannelChange::parse("MALCOLM 10%"),
+            Some(ChannelChange::Malcolm(Some(10)))
+        );
+        assert_eq!(ChannelChange::parse("MALCOLM 900"), None);
+        assert_eq!(
+            ChannelChange::parse("malcolm default"),
+            Some(ChannelChange::Malcolm(None))
+        );
+        assert_eq!(
+            ChannelChange::parse("THREADS YES"),
+            Some(ChannelChange::ThreadsOnly(true))
+        );
+        assert_eq!(
+            ChannelChange::parse("yell 30"),
+            Some(ChannelChange::YellChance(Some(30)))
+        );
+        assert_eq!(ChannelChange::parse("DANCE"), None);
+        assert_eq!(ChannelChange::parse(""), None);
+    }
+
+    #[test]
+    fn settings_survive_the_store() {
+        let mut settings = ChannelSettings::allowlist();
+        settings.apply(ChannelChange::Enabled(true));
+        settings.apply(ChannelChange::Malcolm(Some(50)));
+        let stored = serde_json::to_string(&settings).unwrap();
+        assert_eq!(serde_json::from_str::<ChannelSettings>(&stored).unwrap(), settings);
+        // Settings written before a field existed get its default.
+        let old: ChannelSettings = serde_json::from_str(r#"{"enabled":false}"#).unwrap();
+        assert!(!old.enabled && old.remember);
+    }
+}
@@ -0,0 +1,233 @@
+//! LOUDBOT TAKES ORDERS. This module parses messages addressed to LOUDBOT into
+//! commands, so they don't get mistaken for ordinary shouts.
+//!
+//! There are two ways to address LOUDBOT. Mentioning it (`@LOUDBOT report`) is
+//! the lenient form: only the first word of the command matters. Starting a message
+//! with its name (`LOUDBOT REPORT`) is the strict form: the whole command phrase must
+//! be there, so that shouts like `LOUDBOT WHO IS THE BEST` stay shouts.
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+use std::time::Duration;
+
+use crate::channels::ChannelChange;
+
+/// Things you can tell LOUDBOT to do.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Command {
+    /// List the commands.
+    Help,
+    /// Report on how much yelling has happened.
+    Report,
+    /// Tell everyone who LOUDBOT is.
+    Introduce,
+    /// Forget the last thing yelled.
+    Forget,
+    /// Say who originally shouted the last thing yelled.
+    Who,
+    /// Stop yelling for a while. Holds whatever followed the command, which should say how long.
+    Snooze(String),
+    /// Stop snoozing.
+    Wake,
+    /// List the trigger sets.
+    Triggers,
+    /// Show or change how LOUDBOT behaves in this channel. Holds whatever followed the
+    /// command, which says what to change.
+    Channel(String),
+    /// Reread the trigger definitions.
+    Reload,
+}
+
+/// LOUDBOT's name at the start of a message, and whatever punctuation follows it.
+static NAMED: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?i)^\s*LOUDBOT\b[\s,:]*").unwrap());
+/// A mention at the start of a message, and whatever punctuation follows it.
+static MENTION: Lazy<Regex> = Lazy::new(|| Regex::new(r"^\s*<@[\w|.-]+>[\s,:]*").unwrap());
+/// Numbers in a duration, so `1h30m` can be read like `1 h 30 m`.
+static NUMBERS: Lazy<Regex> = Lazy::new(|| Regex::new(r"(\d+)").unwrap());
+
+/// Builds a command from whatever followed its phrase.
+type Builder = fn(String) -> Command;
+
+/// Each command, with the phrase that invokes it in the strict form. The first word of
+/// each phrase is enough in the lenient form.
+const PHRASES: &[(&str, Builder)] = &[
+    ("HELP", |_| Command::Help),
+    ("REPORT", |_| Command::Report),
+    ("INTRODUCE YOURSELF", |_| Command::Introduce),
+    ("FORGET THAT", |_| Command::Forget),
+    ("WHO SAID THAT", |_| Command::Who),
+    ("SNOOZE", Command::Snooze),
+    ("SHUT UP", Command::Snooze),
+    ("WAKE UP", |_| Command::Wake),
+    ("TRIGGERS", |_| Command::Triggers),
+    ("CHANNEL", Command::Channel),
+    ("RELOAD", |_| Command::Reload),
+];
+
+impl Command {
+    /// Parse a message that starts with LOUDBOT's name. This is the strict form.
+    pub fn parse(text: &str) -> Option<Command> {
+        let found = NAMED.find(text)?;
+        Command::from_phrase(&text[found.end()..], true)
+    }
+
+    /// Parse the text of an `app_mention` event, which starts with a mention of LOUDBOT.
+    /// This is the lenient form.
+    pub fn parse_mention(text: &str) -> Option<Command> {
+        let found = MENTION.find(text)?;
+        Command::from_phrase(&text[found.end()..], false)
+    }
+
+    fn from_phrase(rest: &str, strict: bool) -> Option<Command> {
+        let words: Vec<&str> = rest.split_whitespace().collect();
+        PHRASES.iter().find_map(|(phrase, make)| {
+            let wanted: Vec<&str> = phrase.split(' ').collect();
+            let needed = if strict { wanted.len() } else { 1 };
+            let matched = words.len() >= needed
+                && wanted.iter().zip(words.iter()).take(needed).all(|(want, got)| {
+                    got.trim_matches(|c: char| !c.is_alphanumeric())
+                        .eq_ignore_ascii_case(want)
+                });
+            if !matched {
+                return None;
+            }
+            // In the lenient form, skip as much of the full phrase as is actually there.
+            let skip = wanted
+                .iter()
+                .zip(words.iter())
+                .take_while(|(want, got)| got.eq_ignore_ascii_case(want))
+                .count()
+                .max(needed);
+            let command = make(words[skip..].join(" "));
+            (!strict || command.makes_sense()).then_some(command)
+        })
+    }
+
+    /// Whether whatever followed the command's phrase is something it understands. In
+    /// the strict form it has to be, or the message is just a shout that happens to
+    /// start like a command, such as `LOUDBOT SHUT UP ABOUT CATS`.
+    fn makes_sense(&self) -> bool {
+        match self {
+            Command::Snooze(how_long) => how_long.trim().is_empty() || duration(how_long).is_some(),
+            Command::Channel(change) => change.trim().is_empty() || ChannelChange::parse(change).is_some(),
+            _ => true,
+        }
+    }
+
+    /// Every command, explained in LOUDBOT's own words.
+    pub fn help() -> String {
+        [
+            "YOU CAN MENTION ME OR START A MESSAGE WITH MY NAME.",
+            "`HELP`: THIS.",
+            "`REPORT`: HOW MUCH I HAVE YELLED.",
+            "`INTRODUCE YOURSELF`: WHO I AM.",
+            "`FORGET THAT`: FORGET THE LAST THING I YELLED.",
+            "`WHO SAID THAT`: WHO TAUGHT ME THE LAST THING I YELLED.",
+            "`SHUT UP FOR 2 HOURS`: STOP YELLING IN HERE FOR A WHILE.",
+            "`WAKE UP`: START YELLING IN HERE AGAIN.",
+            "`TRIGGERS`: WHAT ELSE I KNOW ABOUT.",
+            "`CHANNEL`: HOW I BEHAVE IN HERE. ADMINS CAN SAY `CHANNEL OFF`, `CHANNEL REMEMBER OFF`, `CHANNEL TRIGGERS OFF`, `CHANNEL MALCOLM 10`, `CHANNEL YELL 30`, OR `CHANNEL THREADS ON`.",
+            "`RELOAD`: REREAD MY TRIGGERS. ADMINS ONLY.",
+        ]
+        .join("\n")
+    }
+}
+
+/// Read a duration the way people say them: `for 2 hours`, `90 minutes`, `an hour and
+/// 30 minutes`, `1h30m`. Returns None unless all of it made sense.
+pub fn duration(text: &str) -> Option<Duration> {
+    let spaced = NUMBERS.replace_all(text, " $1 ").to_lowercase();
+    let mut total: u64 = 0;
+    let mut amount: Option<u64> = None;
+    for word in spaced.split(|c: char| c.is_whitespace() || c == ',' || c == '.') {
+        match (word, amount) {
+            ("" | "for" | "and", _) => {}
+            ("a" | "an" | "one", None) => amount = Some(1),
+            (number, None) => amount = Some(number.parse().ok()?),
+            (unit, Some(n)) => {
+                let seconds = match unit {
+                    "s" | "sec" | "secs" | "second" | "seconds" => 1,
+                    "m" | "min" | "mins" | "minute" | "minutes" => 60,
+                    "h" | "hr" | "hrs" | "hour" | "hours" => 60 * 60,
+                    "d" | "day" | "days" => 60 * 60 * 24,
+                    _ => return None,
+                };
+                total = n.checked_mul(seconds).and_then(|seconds| total.checked_add(seconds))?;
+                amount = None;
+            }
+        }
+    }
+    if total == 0 || amount.is_some() {
+        return None;
+    }
+    Some(Duration::from_secs(total))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mentions_are_lenient() {
+        assert_eq!(Command::parse_mention("<@U123> report"), Some(Command::Report));
+        assert_eq!(Command::parse_mention("<@U123>: HELP ME"), Some(Command::Help));
+        assert_eq!(Command::parse_mention("<@U123> forget"), Some(Command::Forget));
+        assert_eq!(Command::parse_mention("<@U123> who"), Some(Command::Who));
+        assert_eq!(Command::parse_mention("<@U123> triggers"), Some(Command::Triggers));
+        assert_eq!(Command::parse_mention("<@U123> introduce"), Some(Command::Introduce));
+        assert_eq!(
+            Command::parse_mention("<@U123> snooze 2 hours"),
+            Some(Command::Snooze("2 hours".to_string()))
+        );
+        assert_eq!(
+            Command::parse_mention("<@U123> shut up for 10 minutes"),
+            Some(Command::Snooze("for 10 minutes".to_string()))
+        );
+        assert_eq!(
+            Command::parse_mention("<@U123> channel remember off"),
+            Some(Command::Channel("remember off".to_string()))
+        );
+        assert_eq!(Command::parse_mention("<@U123> dance"), None);
+        assert_eq!(Command::parse_mention("report"), None);
+    }
+
+    #[test]
+    fn durations_are_read() {
+        assert_eq!(duration("FOR 2 HOURS"), Some(Duration::from_secs(2 * 60 * 60)));
+        assert_eq!(duration("10 minutes"), Some(Duration::from_secs(600)));
+        assert_eq!(
+            duration("for an hour and 30 minutes"),
+            Some(Duration::from_secs(90 * 60))
+        );
+        assert_eq!(duration("1h30m"), Some(Duration::from_secs(90 * 60)));
+        assert_eq!(duration("a day"), Some(Duration::from_secs(24 * 60 * 60)));
+        assert_eq!(duration(""), None);
+        assert_eq!(duration("FOR A WHILE"), None);
+        assert_eq!(duration("2"), None);
+        assert_eq!(duration("forever"), None);
+        assert_eq!(duration("199999999999999 days and 199999999999999 days"), None);
+    }
+
+    #[test]
+    fn names_are_strict() {
+        assert_eq!(Command::parse("LOUDBOT REPORT"), Some(Command::Report));
+        assert_eq!(Command::parse("loudbot, introduce yourself"), Some(Command::Introduce));
+        assert_eq!(Command::parse("LOUDBOT FORGET THAT"), Some(Command::Forget));
+        assert_eq!(Command::parse("LOUDBOT WHO SAID THAT?"), Some(Command::Who));
+        assert_eq!(
+            Command::parse("LOUDBOT SHUT UP FOR 2 HOURS"),
+            Some(Command::Snooze("FOR 2 HOURS".to_string()))
+        );
+        assert_eq!(Command::parse("LOUDBOT SHUT UP"), Some(Command::Snooze(String::new())));
+        assert_eq!(Command::parse("LOUDBOT SHUT UP ABOUT CATS"), None);
+        assert_eq!(
+            Command::parse("LOUDBOT CHANNEL OFF"),
+            Some(Command::Channel("OFF".to_string()))
+        );
+        assert_eq!(Command::parse("LOUDBOT CHANNEL SURFING IS GREAT"), None);
+        assert_eq!(Command::parse("LOUDBOT WHO IS THE BEST"), None);
+        assert_eq!(Command::parse("LOUDBOT FORGET IT"), None);
+        assert_eq!(Command::parse("I WANT A LOUDBOT REPORT"), None);
+        assert_eq!(Command::parse("LOUDBOTS ARE GREAT"), None);
+    }
+}
@@ -0,0 +1,142 @@
+//! LOUDBOT PLAYS DICE WITH THE UNIVERSE. Everything LOUDBOT leaves to chance goes through
+//! a `Dice`, so tests can load them and bug reports can be replayed with the same seed.
+//! The one exception is which member of a set the store hands back, which is up to the
+//! store.
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use std::fmt::Debug;
+use std::sync::Mutex;
+
+/// A source of chance.
+pub trait Dice: Send + Sync + Debug {
+    /// Roll a d100: a number from 1 to 100.
+    fn roll(&self) -> u8;
+
+    /// A number from zero up to but not including `n`. Zero if `n` is.
+    fn below(&self, n: u64) -> u64;
+
+    /// A number from zero up to but not including one.
+    fn fraction(&self) -> f64;
+
+    /// Roll against a percentage chance. A chance of zero never comes up.
+    fn chance(&self, chance: u8) -> bool {
+        chance > 0 && self.roll() <= chance
+    }
+}
+
+impl dyn Dice + '_ {
+    /// Pick one of these at random.
+    pub fn choose<'a, T>(&self, items: &'a [T]) -> Option<&'a T> {
+        items.get(self.below(items.len() as u64) as usize)
+    }
+}
+
+/// Fair dice, rolled with the thread's random number generator.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FairDice;
+
+impl Dice for FairDice {
+    fn roll(&self) -> u8 {
+        rand::thread_rng().gen_range(1..=100)
+    }
+
+    fn below(&self, n: u64) -> u64 {
+        if n == 0 {
+            return 0;
+        }
+        rand::thread_rng().gen_range(0..n)
+    }
+
+    fn fraction(&self) -> f64 {
+        rand::thread_rng().gen()
+    }
+}
+
+/// Fair dice that roll the same sequence every time for the same seed.
+#[derive(Debug)]
+pub struct SeededDice {
+    rng: Mutex<StdRng>,
+}
+
+impl SeededDice {
+    pub fn new(seed: u64) -> Self {
+        SeededDice {
+            rng: Mutex::new(StdRng::seed_from_u64(seed)),
+        }
+    }
+
+    fn rng(&self) -> std::sync::MutexGuard<'_, StdRng> {
+        self.rng.lock().expect("dice lock poisoned")
+    }
+}
+
+impl Dice for SeededDice {
+    fn roll(&self) -> u8 {
+        self.rng().gen_range(1..=100)
+    }
+
+    fn below(&self, n: u64) -> u64 {
+        if n == 0 {
+            return 0;
+        }
+        self.rng().gen_range(0..n)
+    }
+
+    fn fraction(&self) -> f64 {
+        self.rng().gen()
+    }
+}
+
+/// Dice that always roll the same number. Everything else they decide is just as far
+/// along: dice loaded with 1 always pick the first thing, and dice loaded with 100
+/// pick something near the end.
+#[derive(Clone, Copy, Debug)]
+pub struct LoadedDice(pub u8);
+
+impl Dice for LoadedDice {
+    fn roll(&self) -> u8 {
+        self.0
+    }
+
+    fn below(&self, n: u64) -> u64 {
+        (self.fraction() * n as f64) as u64
+    }
+
+    fn fraction(&self) -> f64 {
+        (self.0.clamp(1, 100) - 1) as f64 / 100.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seeded_dice_repeat_themselves() {
+        let first = SeededDice::new(9000);
+        let second = SeededDice::new(9000);
+        let rolls: Vec<u8> = (0..20).map(|_| first.roll()).collect();
+        assert_eq!(rolls, (0..20).map(|_| second.roll()).collect::<Vec<u8>>());
+        assert!(rolls.iter().all(|r| (1..=100).contains(r)));
+    }
+
+    #[test]
+    fn chances_come_up_about_as_often_as_they_should() {
+        let dice = SeededDice::new(2014);
+        let hits = (0..10_000).filter(|_| dice.chance(30)).count();
+        assert!((2_800..3_200).contains(&hits), "{hits} hits");
+        assert!(!(0..1_000).any(|_| dice.chance(0)));
+        assert!((0..1_000).all(|_| dice.chance(100)));
+    }
+
+    #[test]
+    fn loaded_dice_choose_predictably() {
+        let items = ["first", "second", "third", "fourth"];
+        let dice: &dyn Dice = &LoadedDice(1);
+        assert_eq!(dice.choose(&items), Some(&"first"));
+        let dice: &dyn Dice = &LoadedDice(100);
+        assert_eq!(dice.choose(&items), Some(&"fourth"));
+        assert_eq!(dice.choose::<&str>(&[]), None);
+    }
+}
@@ -0,0 +1,165 @@
+//! The Slack event envelope, shared by every way Slack can deliver events to us.
+use serde::{Deserialize, Deserializer};
+use slack_api as slack;
+
+use std::collections::HashMap;
+
+/// The parts of an incoming Slack event callback that we care about. Slack sends the same
+/// structure as the body of an Events API post and as the payload of a Socket Mode envelope.
+#[derive(Deserialize, Debug)]
+pub struct IncomingEvent {
+    /// Deprecated verification token; only checked if we have no signing secret.
+    pub token: Option<String>,
+    /// Type of the incoming message event.
+    #[serde(rename = "type")]
+    pub message_type: Option<String>,
+    /// Full event payload.
+    pub event: Option<SlackEvent>,
+    /// Unique id for this event; the same across every delivery of it.
+    pub event_id: Option<String>,
+    /// When the event happened, in epoch seconds.
+    pub event_time: Option<i64>,
+    /// The workspace the event happened in.
+    pub team_id: Option<String>,
+    /// Who the event is for. Our own bot user is in here.
+    #[serde(default)]
+    pub authorizations: Vec<Authorization>,
+    /// Which delivery attempt this is, if Slack is retrying. Slack sends this outside the
+    /// event callback, so the front end fills it in.
+    #[serde(skip)]
+    pub retry_num: Option<u32>,
+    /// Why Slack is retrying, if it is.
+    #[serde(skip)]
+    pub retry_reason: Option<String>,
+    /// The remainder of the envelope, which is only needed sometimes.
+    #[serde(flatten)]
+    pub rest: HashMap<String, serde_json::Value>,
+}
+
+/// The events we know how to handle, sorted by their `type` field.
+#[derive(Debug)]
+pub enum SlackEvent {
+    /// A message in a channel.
+    Message(slack::Message),
+    /// A message that mentions LOUDBOT.
+    AppMention(slack::MessageStandard),
+    /// Someone added an emoji reaction to something.
+    ReactionAdded(ReactionAdded),
+    /// Anything else, which we ignore. Holds the event type.
+    Other(String),
+}
+
+impl<'de> Deserialize<'de> for SlackEvent {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let value = serde_json::Value::deserialize(deserializer)?;
+        let event_type = value["type"].as_str().unwrap_or_default().to_string();
+        // We don't want one event we can't read to fail the whole envelope, so anything
+        // that doesn't parse is demoted to Other.
+        let parsed = match event_type.as_str() {
+            "message" => serde_json::from_value(value).map(SlackEvent::Message),
+            "app_mention" => serde_json::from_value(value).map(SlackEvent::AppMention),
+            "reaction_added" => serde_json::from_value(value).map(SlackEvent::ReactionAdded),
+            _ => return Ok(SlackEvent::Other(event_type)),
+        };
+        Ok(parsed.unwrap_or_else(|e| {
+            log::info!("unable to read {event_type} event: {:?}", e);
+            SlackEvent::Other(event_type)
+        }))
+    }
+}
+
+/// An installation of our app that an event is visible to.
+#[derive(Deserialize, Debug)]
+pub struct Authorization {
+    pub team_id: Option<String>,
+    /// The bot user for this installation.
+    pub user_id: Option<String>,
+    #[serde(default)]
+    pub is_bot: bool,
+}
+
+impl IncomingEvent {
+    /// Our own bot user id, if Slack told us what it is.
+    pub fn bot_user(&self) -> Option<&str> {
+        self.authorizations
+            .iter()
+            .find(|a| a.is_bot)
+            .and_then(|a| a.user_id.as_deref())
+    }
+}
+
+/// A `reaction_added` event.
+#[derive(Deserialize, Debug)]
+pub struct ReactionAdded {
+    /// Who reacted.
+    pub user: String,
+    /// The emoji name, without colons.
+    pub reaction: String,
+    /// Who posted the thing reacted to.
+    pub item_user: Option<String>,
+    /// The thing reacted to.
+    pub item: ReactionItem,
+}
+
+/// The thing a reaction was added to.
+#[derive(Deserialize, Debug)]
+pub struct ReactionItem {
+    /// `message`, `file`, or `file_comment`.
+    #[serde(rename = "type")]
+    pub item_type: String,
+    pub channel: Option<String>,
+    pub ts: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn events_are_sorted_by_type() {
+        let reaction: SlackEvent = serde_json::from_value(serde_json::json!({
+            "type": "reaction_added",
+            "user": "U123",
+            "reaction": "wastebasket",
+            "item_user": "U456",
+            "item": { "type": "message", "channel": "C123", "ts": "1360782400.498405" },
+            "event_ts": "1360782804.083113",
+        }))
+        .unwrap();
+        match reaction {
+            SlackEvent::ReactionAdded(r) => {
+                assert_eq!(r.reaction, "wastebasket");
+                assert_eq!(r.item.ts.as_deref(), Some("1360782400.498405"));
+            }
+            _ => unreachable!("we should have read a reaction"),
+        }
+
+        let message: SlackEvent = serde_json::from_value(serde_json::json!({
+            "type": "message",
+            "channel": "C123",
+            "user": "U123",
+            "text": "HELLO",
+        }))
+        .unwrap();
+        assert!(matches!(message, SlackEvent::Message(_)));
+
+        let mention: SlackEvent = serde_json::from_value(serde_json::json!({
+            "type": "app_mention",
+            "channel": "C123",
+            "user": "U123",
+            "text": "<@U0LAN0Z89> report",
+            "ts": "1515449522.000016",
+        }))
+        .unwrap();
+        assert!(matches!(mention, SlackEvent::AppMention(_)));
+
+        let other: SlackEvent = serde_json::from_value(serde_json::json!({ "type": "team_join" })).unwrap();
+        assert!(matches!(other, SlackEvent::Other(t) if t == "team_join"));
+
+        let broken: SlackEvent = serde_json::from_value(serde_json::json!({ "type": "reaction_added" })).unwrap();
+        assert!(matches!(broken, SlackEvent::Other(_)));
+    }
+}
@@ -1,2 +1,10 @@
+mod events;
+pub use events::{Authorization, IncomingEvent, ReactionAdded, ReactionItem, SlackEvent};
+mod queue;
+pub use queue::{EventQueue, QueueStats};
+mod slack_oauth;
+pub use slack_oauth::{Installation, InstalledTeam, OAuthSettings, SCOPES};
+mod slack_socket;
+pub use slack_socket::LoudbotSocket;
 mod slack_webhooks;
 pub use slack_webhooks::LoudbotSlack;
@@ -0,0 +1,186 @@
+//! A bounded pool of workers that handle Slack events in the background, so that the
+//! front ends can acknowledge Slack right away instead of making it wait on us.
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::{IncomingEvent, LoudbotSlack};
+
+/// Running totals for the queue, updated by the front ends and the workers.
+#[derive(Debug, Default)]
+struct Counters {
+    enqueued: AtomicU64,
+    rejected: AtomicU64,
+    handled: AtomicU64,
+    failed: AtomicU64,
+}
+
+/// A point-in-time look at how the queue is doing.
+#[derive(Debug, Serialize)]
+pub struct QueueStats {
+    /// Events accepted onto the queue.
+    pub enqueued: u64,
+    /// Events turned away because the queue was full or shutting down.
+    pub rejected: u64,
+    /// Events the workers handled without error.
+    pub handled: u64,
+    /// Events the workers failed to handle.
+    pub failed: u64,
+    /// Events waiting for a worker right now.
+    pub depth: usize,
+    /// How many events can wait before we start turning them away.
+    pub capacity: usize,
+    /// How many workers are pulling from the queue.
+    pub workers: usize,
+}
+
+pub struct EventQueue {
+    /// Dropped on shutdown, which is how the workers learn to stop.
+    sender: Mutex<Option<mpsc::Sender<IncomingEvent>>>,
+    workers: Mutex<Vec<JoinHandle<()>>>,
+    counters: Arc<Counters>,
+    capacity: usize,
+    worker_count: usize,
+}
+
+impl EventQueue {
+    /// Start `worker_count` workers handling events with the given face. At most `capacity`
+    /// events can wait for a worker.
+    pub fn new(face: Arc<LoudbotSlack>, worker_count: usize, capacity: usize) -> Self {
+        let worker_count = worker_count.max(1);
+        let capacity = capacity.max(1);
+        let (sender, receiver) = mpsc::channel::<IncomingEvent>(capacity);
+        let receiver = Arc::new(tokio::sync::Mutex::new(receiver));
+        let counters = Arc::new(Counters::default());
+
+        let workers = (0..worker_count)
+            .map(|id| {
+                let receiver = receiver.clone();
+                let counters = counters.clone();
+                let face = face.clone();
+                tokio::spawn(async move {
+                    loop {
+                        // The lock is released as soon as we have an event, so the other
+                        // workers can pick up the next one while we handle this one.
+                        let next = receiver.lock().await.recv().await;
+                        let incoming = match next {
+                            Some(incoming) => incoming,
+                            None => break,
+                        };
+                        match face.handle_event(incoming).await {
+                            Ok(_) => {
+                                log::debug!("worker {id} handled callback successfully");
+                                counters.handled.fetch_add(1, Ordering::Relaxed);
+                            }
+                            Err(e) => {
+                                log::warn!("worker {id} error handling callback: {:?}", e);
+                                counters.failed.fetch_add(1, Ordering::Relaxed);
+                            }
+                        }
+                    }
+                    log::debug!("worker {id} is done");
+                })
+            })
+            .collect();
+
+        EventQueue {
+            sender: Mutex::new(Some(sender)),
+            workers: Mutex::new(workers),
+            counters,
+            capacity,
+            worker_count,
+        }
+    }
+
+    /// Put an event on the queue without waiting. Fails if the queue is full or draining.
+    pub fn enqueue(&self, incoming: IncomingEvent) -> Result<()> {
+        let sender = self.sender.lock().expect("queue sender lock poisoned");
+        let result = match sender.as_ref() {
+            Some(sender) => sender.try_send(incoming).map_err(|e| match e {
+                mpsc::error::TrySendError::Full(_) => anyhow!("the event queue is full"),
+                mpsc::error::TrySendError::Closed(_) => anyhow!("the event queue is closed"),
+            }),
+            None => Err(anyhow!("the event queue is draining")),
+        };
+        match result {
+            Ok(_) => self.counters.enqueued.fetch_add(1, Ordering::Relaxed),
+            Err(_) => self.counters.rejected.fetch_add(1, Ordering::Relaxed),
+        };
+        result
+    }
+
+    pub fn stats(&self) -> QueueStats {
+        let depth = match self.sender.lock().expect("queue sender lock poisoned").as_ref() {
+            Some(sender) => self.capacity - sender.capacity(),
+            None => 0,
+        };
+        QueueStats {
+            enqueued: self.counters.enqueued.load(Ordering::Relaxed),
+            rejected: self.counters.rejected.load(Ordering::Relaxed),
+            handled: self.counters.handled.load(Ordering::Relaxed),
+            failed: self.counters.failed.load(Ordering::Relaxed),
+            depth,
+            capacity: self.capacity,
+            workers: self.worker_count,
+        }
+    }
+
+    /// Stop accepting events, then wait for the workers to finish everything already queued.
+    pub async fn drain(&self) {
+        drop(self.sender.lock().expect("queue sender lock poisoned").take());
+        let workers: Vec<JoinHandle<()>> = self
+            .workers
+            .lock()
+            .expect("queue worker lock poisoned")
+            .drain(..)
+            .collect();
+        for worker in workers {
+            let _ = worker.await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Loudbot, MemoryStore};
+
+    fn quiet_event() -> IncomingEvent {
+        serde_json::from_value(serde_json::json!({
+            "type": "event_callback",
+            "event": {
+                "type": "message",
+                "channel": "C123",
+                "user": "U123",
+                "text": "just chatting quietly",
+            },
+        }))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn drains_everything_it_accepted() {
+        let brain = Loudbot::with_store(Arc::new(MemoryStore::new()), 0);
+        let face = Arc::new(LoudbotSlack::new(Some("xoxb-test".to_string()), None, None, brain));
+        let queue = EventQueue::new(face, 2, 10);
+
+        for _ in 0..5 {
+            queue.enqueue(quiet_event()).unwrap();
+        }
+        queue.drain().await;
+        assert!(
+            queue.enqueue(quiet_event()).is_err(),
+            "a drained queue takes no more events"
+        );
+
+        let stats = queue.stats();
+        assert_eq!(stats.enqueued, 5);
+        assert_eq!(stats.handled, 5);
+        assert_eq!(stats.rejected, 1);
+        assert_eq!(stats.depth, 0);
+    }
+}
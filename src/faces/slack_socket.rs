@@ -0,0 +1,214 @@
+//! A Slack Socket Mode front end. Instead of waiting for Slack to post events to a
+//! public url, we open a websocket to Slack and Slack sends events down it.
+use anyhow::{anyhow, Result};
+use futures::{SinkExt, StreamExt};
+use serde::Deserialize;
+use tokio_tungstenite::tungstenite::Message as WsMessage;
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::{EventQueue, IncomingEvent};
+
+/// The api method that hands out websocket urls.
+const CONNECTIONS_OPEN: &str = "https://slack.com/api/apps.connections.open";
+/// How long to wait before trying again after a connection attempt fails.
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// The response to `apps.connections.open`.
+#[derive(Deserialize, Debug)]
+struct ConnectionsOpen {
+    ok: bool,
+    url: Option<String>,
+    error: Option<String>,
+}
+
+/// Everything Slack sends us over the socket arrives wrapped in one of these.
+#[derive(Deserialize, Debug)]
+struct Envelope {
+    /// Present if Slack expects an acknowledgement.
+    envelope_id: Option<String>,
+    #[serde(rename = "type")]
+    envelope_type: String,
+    /// For `events_api` envelopes, the same event callback the Events API would post.
+    payload: Option<serde_json::Value>,
+    /// For `disconnect` envelopes, why Slack is hanging up on us.
+    reason: Option<String>,
+    /// Which delivery attempt this is, if Slack is retrying.
+    retry_attempt: Option<u32>,
+    /// Why Slack is retrying, if it is.
+    retry_reason: Option<String>,
+}
+
+/// Why a socket mode connection ended.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Hangup {
+    /// Slack asked us to reconnect, for the given reason.
+    Disconnect(String),
+    /// The socket closed without warning.
+    Closed,
+}
+
+pub struct LoudbotSocket {
+    /// the app-level token that lets us open socket mode connections
+    app_token: String,
+    /// the queue our events go onto for the workers
+    queue: Arc<EventQueue>,
+}
+
+impl LoudbotSocket {
+    pub fn new(app_token: String, queue: Arc<EventQueue>) -> Self {
+        LoudbotSocket { app_token, queue }
+    }
+
+    /// Listen for events forever, reconnecting whenever Slack asks us to or the connection drops.
+    pub async fn run(&self) -> Result<()> {
+        loop {
+            let url = match self.open_connection().await {
+                Ok(url) => url,
+                Err(e) => {
+                    log::warn!("unable to open a socket mode connection: {:?}", e);
+                    tokio::time::sleep(RECONNECT_DELAY).await;
+                    continue;
+                }
+            };
+            match self.serve(&url).await {
+                Ok(Hangup::Disconnect(reason)) => log::info!("slack asked us to reconnect; reason: {reason}"),
+                Ok(Hangup::Closed) => log::info!("socket closed; reconnecting"),
+                Err(e) => {
+                    log::warn!("socket mode connection failed: {:?}", e);
+                    tokio::time::sleep(RECONNECT_DELAY).await;
+                }
+            }
+        }
+    }
+
+    /// Ask Slack for a fresh websocket url.
+    async fn open_connection(&self) -> Result<String> {
+        let client = reqwest::Client::new();
+        let response: ConnectionsOpen = client
+            .post(CONNECTIONS_OPEN)
+            .bearer_auth(&self.app_token)
+            .send()
+            .await?
+            .json()
+            .await?;
+        match response {
+            ConnectionsOpen {
+                ok: true,
+                url: Some(url),
+                ..
+            } => Ok(url),
+            _ => Err(anyhow!(
+                "apps.connections.open failed: {}",
+                response.error.unwrap_or_default()
+            )),
+        }
+    }
+
+    /// Serve a single socket mode connection until it ends.
+    pub async fn serve(&self, url: &str) -> Result<Hangup> {
+        let (mut socket, _) = tokio_tungstenite::connect_async(url).await?;
+
+        while let Some(frame) = socket.next().await {
+            let text = match frame? {
+                WsMessage::Text(text) => text,
+                WsMessage::Close(_) => return Ok(Hangup::Closed),
+                _ => continue,
+            };
+            let envelope: Envelope = match serde_json::from_str(&text) {
+                Ok(v) => v,
+                Err(e) => {
+                    log::warn!("unparseable socket mode envelope: {:?}", e);
+                    continue;
+                }
+            };
+
+            // Slack wants its ack within three seconds, so we send it before doing any work.
+            if let Some(id) = &envelope.envelope_id {
+                let ack = serde_json::json!({ "envelope_id": id });
+                socket.send(WsMessage::Text(ack.to_string())).await?;
+            }
+
+            match envelope.envelope_type.as_str() {
+                "hello" => log::info!("LOUDBOT IS LISTENING ON A SOCKET"),
+                "disconnect" => return Ok(Hangup::Disconnect(envelope.reason.unwrap_or_default())),
+                "events_api" => self.enqueue_payload(envelope),
+                other => log::info!("unhandled socket mode envelope type: {other}"),
+            }
+        }
+
+        Ok(Hangup::Closed)
+    }
+
+    /// Unwrap an event callback and put it on the queue for the workers. We've already
+    /// acked the envelope, so if the queue is full the event is lost.
+    fn enqueue_payload(&self, envelope: Envelope) {
+        let mut incoming: IncomingEvent = match envelope.payload.map(serde_json::from_value) {
+            Some(Ok(v)) => v,
+            _ => {
+                log::warn!("socket mode event did not have a valid payload");
+                return;
+            }
+        };
+        // Slack counts the first delivery as attempt zero here.
+        incoming.retry_num = envelope.retry_attempt.filter(|attempt| *attempt > 0);
+        incoming.retry_reason = envelope.retry_reason;
+        if let Err(e) = self.queue.enqueue(incoming) {
+            log::warn!("dropping socket mode event: {:?}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Loudbot, LoudbotSlack, MemoryStore};
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn acks_envelopes_and_hangs_up_when_asked() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        // A stand-in for Slack's end of the socket.
+        let slack = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+            let hello = serde_json::json!({ "type": "hello" });
+            ws.send(WsMessage::Text(hello.to_string())).await.unwrap();
+            let event = serde_json::json!({
+                "envelope_id": "ENVELOPE1",
+                "type": "events_api",
+                "payload": {
+                    "type": "event_callback",
+                    "event": {
+                        "type": "message",
+                        "channel": "C123",
+                        "user": "U123",
+                        "text": "just chatting quietly",
+                    },
+                },
+            });
+            ws.send(WsMessage::Text(event.to_string())).await.unwrap();
+            let ack = ws.next().await.unwrap().unwrap().into_text().unwrap();
+            let disconnect = serde_json::json!({ "type": "disconnect", "reason": "refresh_requested" });
+            ws.send(WsMessage::Text(disconnect.to_string())).await.unwrap();
+            ack
+        });
+
+        let brain = Loudbot::with_store(Arc::new(MemoryStore::new()), 0);
+        let face = LoudbotSlack::new(Some("xoxb-test".to_string()), None, None, brain);
+        let queue = Arc::new(EventQueue::new(Arc::new(face), 1, 10));
+        let socket = LoudbotSocket::new("xapp-test".to_string(), queue.clone());
+
+        let hangup = socket.serve(&format!("ws://{addr}")).await.unwrap();
+        assert_eq!(hangup, Hangup::Disconnect("refresh_requested".to_string()));
+
+        let ack: serde_json::Value = serde_json::from_str(&slack.await.unwrap()).unwrap();
+        assert_eq!(ack["envelope_id"], "ENVELOPE1");
+
+        queue.drain().await;
+        assert_eq!(queue.stats().handled, 1);
+    }
+}
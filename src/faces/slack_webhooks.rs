@@ -1,43 +1,184 @@
-use crate::Loudbot;
+use super::slack_oauth::{install_state, OAuthSettings};
+use crate::{now, Command, Curated, IncomingEvent, Loudbot, Origin, ReactionAdded, Reply, SlackEvent};
+use anyhow::anyhow;
+use hmac::{Hmac, Mac};
+use regex::Regex;
+use sha2::Sha256;
 use slack::chat::PostMessageRequest;
+use slack::reactions::AddRequest;
 use slack_api as slack;
 
+use std::time::Duration;
+
+/// How far a request timestamp may drift from our clock before we treat it as a replay.
+const REPLAY_WINDOW_SECS: u64 = 60 * 5;
+
 pub struct LoudbotSlack {
-    /// the API token we must send to Slack
-    slack_token: String,
-    /// the verification token Slack must send to us
-    pub verification: String,
+    /// the API token we must send to Slack, unless the workspace gave us its own
+    slack_token: Option<String>,
+    /// the signing secret Slack uses to sign the requests it sends us
+    signing_secret: Option<String>,
+    /// the deprecated verification token Slack sends in the request body
+    verification: Option<String>,
     /// our loudbot brain
     brain: Loudbot,
+    /// how to install ourselves in more workspaces, if we can
+    oauth: Option<OAuthSettings>,
 }
 
 impl LoudbotSlack {
-    pub fn new(slack_token: String, verification: String, brain: Loudbot) -> Self {
+    pub fn new(
+        slack_token: Option<String>,
+        signing_secret: Option<String>,
+        verification: Option<String>,
+        brain: Loudbot,
+    ) -> Self {
         LoudbotSlack {
             slack_token,
+            signing_secret,
             verification,
             brain,
+            oauth: None,
         }
     }
 
-    // Given data about an incoming request, verify that it came from Slack.
-    pub async fn verify_request() -> anyhow::Result<bool> {
-        // TODO unimplemented
-        Ok(false)
+    /// Let workspaces install us with Slack's OAuth flow.
+    pub fn with_oauth(mut self, oauth: OAuthSettings) -> Self {
+        self.oauth = Some(oauth);
+        self
+    }
+
+    /// The token to use when talking to Slack about a workspace: the one it gave us when
+    /// it installed us, or our own if it didn't.
+    async fn token_for(&self, team: Option<&str>) -> anyhow::Result<String> {
+        if let Some(team) = team {
+            if let Some(token) = self.brain.team_token(team).await? {
+                return Ok(token);
+            }
+        }
+        self.slack_token
+            .clone()
+            .ok_or_else(|| anyhow!("no slack token for team {:?}", team))
+    }
+
+    /// Start installing us in a workspace. Returns the url to send the installer to.
+    pub async fn begin_install(&self) -> anyhow::Result<String> {
+        let oauth = self
+            .oauth
+            .as_ref()
+            .ok_or_else(|| anyhow!("we are not set up for oauth"))?;
+        let state = install_state();
+        self.brain.expect_install(&state).await?;
+        oauth.authorize_url(&state)
+    }
+
+    /// Finish an install Slack has sent back to us, and keep the workspace's token.
+    /// Returns the name of the workspace.
+    pub async fn finish_install(&self, code: &str, state: &str) -> anyhow::Result<String> {
+        let oauth = self
+            .oauth
+            .as_ref()
+            .ok_or_else(|| anyhow!("we are not set up for oauth"))?;
+        if !self.brain.expected_install(state).await? {
+            return Err(anyhow!("install state `{state}` is not one we handed out"));
+        }
+        let installed = oauth.exchange(code).await?;
+        self.brain.install(&installed.team.id, &installed.access_token).await?;
+        log::info!("LOUDBOT IS NOW IN {}", installed.team.id);
+        Ok(installed.team.name.unwrap_or(installed.team.id))
+    }
+
+    /// Given data about an incoming request, verify that it came from Slack.
+    ///
+    /// If we have a signing secret, the `X-Slack-Signature` header must be a valid v0
+    /// signature of the raw body and the `X-Slack-Request-Timestamp` must be recent.
+    /// If all we have is the legacy verification token, we compare it against the token
+    /// in the body instead.
+    pub fn verify_request(
+        &self,
+        timestamp: Option<&str>,
+        signature: Option<&str>,
+        body: &[u8],
+        token: Option<&str>,
+    ) -> anyhow::Result<bool> {
+        if let Some(secret) = &self.signing_secret {
+            match (timestamp, signature) {
+                (Some(timestamp), Some(signature)) => {
+                    check_signature(secret, timestamp, signature, body, now().as_secs())
+                }
+                _ => {
+                    log::info!("request is missing its signature headers");
+                    Ok(false)
+                }
+            }
+        } else if let Some(verification) = &self.verification {
+            Ok(token == Some(verification.as_str()))
+        } else {
+            Ok(false)
+        }
     }
 
     /// If we have a welcome channel, send a toast to it.
     pub async fn maybe_toast(&self) -> anyhow::Result<bool> {
-        if let Ok(toast) = std::env::var("WELCOME_CHANNEL") {
-            self.send_message(&toast, "THIS LOUDBOT IS NOW SCUTTLING", None).await
+        if let (Ok(toast), Some(token)) = (std::env::var("WELCOME_CHANNEL"), &self.slack_token) {
+            send_message(token, &toast, "THIS LOUDBOT IS NOW SCUTTLING", None)
+                .await
+                .map(|_| true)
         } else {
             Ok(false)
         }
     }
 
+    /// Process an event callback from Slack, however it reached us. Slack delivers events
+    /// at least once, so we drop any event we've already seen, and any retry we can't
+    /// tell apart from the original.
+    pub async fn handle_event(&self, incoming: IncomingEvent) -> anyhow::Result<bool> {
+        if let Some(retry) = incoming.retry_num {
+            log::info!(
+                "slack is retrying event {:?}; attempt {retry}; reason: {}",
+                incoming.event_id,
+                incoming.retry_reason.as_deref().unwrap_or("unknown")
+            );
+        }
+        if !self
+            .brain
+            .first_delivery(incoming.event_id.as_deref(), incoming.retry_num)
+            .await
+        {
+            log::info!("dropping duplicate delivery of event {:?}", incoming.event_id);
+            return Ok(false);
+        }
+
+        let bot_user = incoming.bot_user().map(|u| u.to_string());
+        let team = incoming.team_id.as_deref();
+        match incoming.event {
+            Some(SlackEvent::Message(mut event)) => {
+                if let slack::Message::Standard(prompt) = &mut event {
+                    adopt(prompt, team);
+                }
+                self.handle_message(event, bot_user.as_deref()).await
+            }
+            Some(SlackEvent::AppMention(mut prompt)) => {
+                adopt(&mut prompt, team);
+                self.handle_mention(prompt).await
+            }
+            Some(SlackEvent::ReactionAdded(reaction)) => self.handle_reaction(reaction, team).await,
+            Some(SlackEvent::Other(event_type)) => {
+                log::debug!("ignoring {event_type} event");
+                Ok(false)
+            }
+            None => {
+                log::warn!("incoming event did not have a valid structure {:?}", incoming);
+                Ok(false)
+            }
+        }
+    }
+
     /// Process an incoming message from slack and make decisions based on its envelope.
+    /// Messages that mention our bot user arrive again as `app_mention` events, so we
+    /// leave those to `handle_mention`.
     /// Slack-specific
-    pub async fn handle_message(&self, incoming: slack::Message) -> anyhow::Result<bool> {
+    pub async fn handle_message(&self, incoming: slack::Message, bot_user: Option<&str>) -> anyhow::Result<bool> {
         match incoming {
             slack::Message::BotMessage(ref _y) => {
                 log::debug!("skipping bot message");
@@ -47,16 +188,20 @@ impl LoudbotSlack {
                 if let Some(_bot_id) = &prompt.bot_id {
                     log::info!("skipping bot message");
                     Ok(false)
-                } else if prompt.text.is_none() || prompt.channel.is_none() {
-                    Ok(false) // nothing to be done
-                } else {
-                    let text = prompt.text.as_ref().unwrap(); // we know this is safe
-                    let retort = self.brain.process(text).await;
-                    if let Some(r) = retort {
-                        self.yell(prompt, &r).await
-                    } else {
-                        Ok(false)
+                } else if let (Some(text), Some(channel)) = (&prompt.text, &prompt.channel) {
+                    if let Some(bot_user) = bot_user {
+                        if text.contains(&format!("<@{bot_user}>")) {
+                            log::debug!("skipping message that mentions us");
+                            return Ok(false);
+                        }
+                    }
+                    let settings = self.brain.channel_settings(channel).await;
+                    match self.brain.process_in(text, &origin(prompt), &settings).await {
+                        Some(reply) => self.respond(prompt, reply).await,
+                        None => Ok(false),
                     }
+                } else {
+                    Ok(false) // nothing to be done
                 }
             }
             _ => {
@@ -66,42 +211,268 @@ impl LoudbotSlack {
         }
     }
 
+    /// Someone mentioned us, so they want us to do something. If we can't tell what,
+    /// we explain ourselves.
+    pub async fn handle_mention(&self, prompt: slack::MessageStandard) -> anyhow::Result<bool> {
+        if prompt.bot_id.is_some() {
+            return Ok(false);
+        }
+        let text = match &prompt.text {
+            Some(text) => text,
+            None => return Ok(false),
+        };
+        let command = Command::parse_mention(text).unwrap_or(Command::Help);
+        match self.brain.execute(command, &origin(&prompt)).await {
+            Some(reply) => self.respond(&prompt, reply).await,
+            None => Ok(false),
+        }
+    }
+
+    /// Someone in a workspace reacted to something. If it was one of our shouts, let the
+    /// brain decide whether that means anything.
+    pub async fn handle_reaction(&self, reaction: ReactionAdded, team: Option<&str>) -> anyhow::Result<bool> {
+        let (channel, ts) = match (&reaction.item.item_type[..], reaction.item.channel, reaction.item.ts) {
+            ("message", Some(channel), Some(ts)) => (channel, ts),
+            _ => return Ok(false),
+        };
+        // Skin tones don't change what the reaction means.
+        let emoji = reaction.reaction.split("::").next().unwrap_or_default();
+        match self.brain.curate(team, &channel, &ts, &reaction.user, emoji).await? {
+            Curated::Nothing => Ok(false),
+            Curated::Vote(votes) => {
+                log::info!("{votes} votes to forget the shout at {channel}/{ts}");
+                Ok(true)
+            }
+            Curated::Forgotten(shout) => {
+                log::info!("forgot `{shout}` by popular demand");
+                Ok(true)
+            }
+            Curated::Favorited(shout) => {
+                log::info!("`{shout}` is now a little more popular");
+                Ok(true)
+            }
+        }
+    }
+
+    /// Carry out the brain's reply to a prompt.
+    pub async fn respond(&self, prompt: &slack::MessageStandard, reply: Reply) -> anyhow::Result<bool> {
+        let yell = match reply.yell {
+            Some(yell) => yell,
+            None => {
+                return match reply.emoji {
+                    Some(emoji) => self.react(prompt, &emoji).await,
+                    None => Ok(false),
+                }
+            }
+        };
+        if let Some(emoji) = reply.emoji {
+            // A failed reaction shouldn't stop the yell.
+            if let Err(e) = self.react(prompt, &emoji).await {
+                log::warn!("unable to react with :{emoji}:; {:?}", e);
+            }
+        }
+        let thread_ts = if reply.thread {
+            prompt.thread_ts.or(prompt.ts)
+        } else {
+            prompt.thread_ts
+        };
+        if let (Some(after), Some(channel)) = (reply.wake_in, &prompt.channel) {
+            self.set_alarm(after, channel, prompt.team.as_deref()).await?;
+        }
+        let posted = self.yell(prompt, &yell, thread_ts).await?;
+        if let (Some(shout), Some(ts), Some(channel)) = (reply.shout, posted, &prompt.channel) {
+            self.brain.posted(channel, &ts, &shout).await;
+        }
+        Ok(true)
+    }
+
     /// Post a yell and record that we're doing so. Prefer this function to yell.
-    pub async fn yell(&self, prompt: &slack::MessageStandard, retort: &str) -> anyhow::Result<bool> {
+    /// Returns the timestamp of the posted yell, if Slack told us what it was.
+    pub async fn yell(
+        &self,
+        prompt: &slack::MessageStandard,
+        retort: &str,
+        thread_ts: Option<slack::Timestamp>,
+    ) -> anyhow::Result<Option<String>> {
         let channel = prompt.channel.as_ref().unwrap();
         log::info!(
             "yelling: `{retort}`; prompt: `{}`' channel: `{channel}`",
             prompt.text.as_ref().unwrap()
         );
-        let sent = self.send_message(channel, retort, prompt.thread_ts).await?;
+        let token = self.token_for(prompt.team.as_deref()).await?;
+        let posted = send_message(&token, channel, retort, thread_ts).await?;
         self.brain.increment_yells().await;
-        Ok(sent)
+        Ok(posted)
     }
 
-    /// Slack implementation: send a message
-    async fn send_message(
-        &self,
-        channel: &str,
-        text: &str,
-        maybe_ts: Option<slack::Timestamp>,
-    ) -> Result<bool, anyhow::Error> {
-        let message = PostMessageRequest {
+    /// React to a prompt with an emoji.
+    pub async fn react(&self, prompt: &slack::MessageStandard, emoji: &str) -> anyhow::Result<bool> {
+        let channel = prompt.channel.as_deref();
+        log::info!("reacting: `:{emoji}:`; channel: `{}`", channel.unwrap_or_default());
+        let request = AddRequest {
+            name: emoji,
+            file: None,
+            file_comment: None,
             channel,
-            text,
-            thread_ts: maybe_ts,
-            unfurl_links: Some(true),
-            link_names: Some(true),
-            ..PostMessageRequest::default()
+            timestamp: prompt.ts,
         };
 
+        let token = self.token_for(prompt.team.as_deref()).await?;
         let client = slack::default_client()?;
-        let response = slack::chat::post_message(&client, &self.slack_token, &message).await;
-        match response {
+        match slack::reactions::add(&client, &token, &request).await {
             Err(e) => {
-                log::error!("error trying to post message: {:?}", e);
+                log::error!("error trying to add reaction: {:?}", e);
                 Err(anyhow::anyhow!(e))
             }
             Ok(_) => Ok(true),
         }
     }
+
+    /// Add the workspace's custom emoji whose names match the pattern to the brain's
+    /// reaction pool. Returns how many we found. Only our own workspace counts, because
+    /// the pool is shared and custom emoji aren't.
+    pub async fn discover_emoji(&self, pattern: &Regex) -> anyhow::Result<usize> {
+        let token = match &self.slack_token {
+            Some(token) => token,
+            None => return Ok(0),
+        };
+        let client = slack::default_client()?;
+        let response = slack::emoji::list(&client, token)
+            .await
+            .map_err(|e| anyhow::anyhow!(e))?;
+        let found: Vec<String> = response
+            .emoji
+            .unwrap_or_default()
+            .into_keys()
+            .filter(|name| pattern.is_match(name))
+            .collect();
+        let count = found.len();
+        self.brain.add_reactions(found);
+        Ok(count)
+    }
+
+    /// Wake the brain up in a channel once its snooze runs out, and let the channel know.
+    /// If we're restarted before then, the brain notices on the next message instead.
+    async fn set_alarm(&self, after: Duration, channel: &str, team: Option<&str>) -> anyhow::Result<()> {
+        let token = self.token_for(team).await?;
+        let brain = self.brain.clone();
+        let channel = channel.to_string();
+        tokio::spawn(async move {
+            tokio::time::sleep(after).await;
+            if let Some(announcement) = brain.alarm(&channel).await {
+                if let Err(e) = send_message(&token, &channel, &announcement, None).await {
+                    log::warn!("unable to announce that we're awake in {channel}; {:?}", e);
+                }
+            }
+        });
+        Ok(())
+    }
+}
+
+/// Slack implementation: send a message. Returns the posted message's timestamp.
+async fn send_message(
+    token: &str,
+    channel: &str,
+    text: &str,
+    maybe_ts: Option<slack::Timestamp>,
+) -> Result<Option<String>, anyhow::Error> {
+    let message = PostMessageRequest {
+        channel,
+        text,
+        thread_ts: maybe_ts,
+        unfurl_links: Some(true),
+        link_names: Some(true),
+        ..PostMessageRequest::default()
+    };
+
+    let client = slack::default_client()?;
+    let response = slack::chat::post_message(&client, token, &message).await;
+    match response {
+        Err(e) => {
+            log::error!("error trying to post message: {:?}", e);
+            Err(anyhow::anyhow!(e))
+        }
+        Ok(posted) => Ok(posted.ts.map(|ts| ts.to_string())),
+    }
+}
+
+/// Messages don't always say which workspace they're from, but their envelope does.
+fn adopt(prompt: &mut slack::MessageStandard, team: Option<&str>) {
+    if prompt.team.is_none() {
+        prompt.team = team.map(|t| t.to_string());
+    }
+}
+
+/// Everything the brain wants to know about where a Slack message came from.
+fn origin(prompt: &slack::MessageStandard) -> Origin {
+    Origin {
+        team: prompt.team.clone(),
+        channel: prompt.channel.clone(),
+        user: prompt.user.clone(),
+        ts: prompt.ts.map(|ts| ts.to_string()),
+        thread_ts: prompt.thread_ts.map(|ts| ts.to_string()),
+    }
+}
+
+/// Check a Slack v0 request signature: `v0=` followed by the hex HMAC-SHA256 of
+/// `v0:{timestamp}:{body}`, keyed with our signing secret. Stale timestamps fail
+/// the check no matter how good the signature is.
+fn check_signature(secret: &str, timestamp: &str, signature: &str, body: &[u8], now: u64) -> anyhow::Result<bool> {
+    let sent_at: u64 = match timestamp.parse() {
+        Ok(t) => t,
+        Err(_) => {
+            log::info!("request timestamp `{timestamp}` is not a number");
+            return Ok(false);
+        }
+    };
+    if now.abs_diff(sent_at) > REPLAY_WINDOW_SECS {
+        log::info!("request timestamp {sent_at} is outside the replay window");
+        return Ok(false);
+    }
+
+    let expected = match signature.strip_prefix("v0=").map(hex::decode) {
+        Some(Ok(bytes)) => bytes,
+        _ => {
+            log::info!("request signature is not a v0 hex signature");
+            return Ok(false);
+        }
+    };
+
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())?;
+    mac.update(b"v0:");
+    mac.update(timestamp.as_bytes());
+    mac.update(b":");
+    mac.update(body);
+    Ok(mac.verify_slice(&expected).is_ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &str = "8f742231b10e8888abcd99yyyzzz85a5";
+    const BODY: &[u8] = b"token=xyzz0WbapA4vBCDEFasx0q6G&team_id=T1DC2JH3J";
+    const SIGNATURE: &str = "v0=bca5eef5dd737ed259b428b18cd24f679baa18c3fc5f1cb2a6ac9f03e717969a";
+
+    #[test]
+    fn good_signatures_pass() {
+        assert!(check_signature(SECRET, "1531420618", SIGNATURE, BODY, 1531420618).unwrap());
+        assert!(check_signature(SECRET, "1531420618", SIGNATURE, BODY, 1531420618 + 60).unwrap());
+    }
+
+    #[test]
+    fn bad_signatures_fail() {
+        assert!(!check_signature("wrong secret", "1531420618", SIGNATURE, BODY, 1531420618).unwrap());
+        assert!(!check_signature(SECRET, "1531420618", SIGNATURE, b"token=tampered", 1531420618).unwrap());
+        assert!(!check_signature(SECRET, "1531420619", SIGNATURE, BODY, 1531420618).unwrap());
+        assert!(!check_signature(SECRET, "1531420618", "v1=bca5eef5", BODY, 1531420618).unwrap());
+        assert!(!check_signature(SECRET, "1531420618", "v0=not hex", BODY, 1531420618).unwrap());
+        assert!(!check_signature(SECRET, "yesterday", SIGNATURE, BODY, 1531420618).unwrap());
+    }
+
+    #[test]
+    fn replays_are_rejected() {
+        assert!(!check_signature(SECRET, "1531420618", SIGNATURE, BODY, 1531420618 + 301).unwrap());
+        assert!(!check_signature(SECRET, "1531420618", SIGNATURE, BODY, 1531420618 - 301).unwrap());
+    }
 }
@@ -0,0 +1,184 @@
+//! WHERE LOUDBOT KEEPS THINGS. Every key LOUDBOT reads or writes in its store is named
+//! here, under a prefix, so several LOUDBOTs can share one redis without stepping on
+//! each other's shouts. The server, SEED, and PRUNE all agree on names through this.
+
+/// The prefix LOUDBOTs have always used.
+pub const DEFAULT_PREFIX: &str = "LB";
+
+/// The names of LOUDBOT's keys under a given prefix.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Keys {
+    prefix: String,
+}
+
+impl Default for Keys {
+    fn default() -> Self {
+        Keys::new(DEFAULT_PREFIX)
+    }
+}
+
+impl Keys {
+    pub fn new(prefix: impl Into<String>) -> Self {
+        Keys { prefix: prefix.into() }
+    }
+
+    /// Keys under the prefix in the `REDIS_PREFIX` env var, or the default if it's unset.
+    pub fn from_env() -> Self {
+        match std::env::var("REDIS_PREFIX") {
+            Ok(prefix) if !prefix.is_empty() => Keys::new(prefix),
+            _ => Keys::default(),
+        }
+    }
+
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    /// A pattern matching every key under our prefix, for scanning.
+    pub fn pattern(&self) -> String {
+        format!("{}:*", self.prefix)
+    }
+
+    /// The set of core shouts.
+    pub fn yells(&self) -> String {
+        format!("{}:YELLS", self.prefix)
+    }
+
+    /// The count of times we've yelled.
+    pub fn count(&self) -> String {
+        format!("{}:COUNT", self.prefix)
+    }
+
+    /// The count of times the named trigger set has fired.
+    pub fn trigger_count(&self, set: &str) -> String {
+        format!("{}:{set}_COUNT", self.prefix)
+    }
+
+    /// The count of duplicate event deliveries we've dropped.
+    pub fn duplicates(&self) -> String {
+        format!("{}:DUPLICATES", self.prefix)
+    }
+
+    /// The count of retries we've dropped because they had no event id to check.
+    pub fn retries(&self) -> String {
+        format!("{}:RETRIES", self.prefix)
+    }
+
+    /// The count of yells the rate limiter held back.
+    pub fn held_back(&self) -> String {
+        format!("{}:HELD_BACK", self.prefix)
+    }
+
+    /// The count of shouts the rate limiter dropped entirely.
+    pub fn dropped(&self) -> String {
+        format!("{}:DROPPED", self.prefix)
+    }
+
+    /// Marks an event we've already seen.
+    pub fn event(&self, event_id: &str) -> String {
+        format!("{}:EVENT:{event_id}", self.prefix)
+    }
+
+    /// The weighted set of favorite shouts.
+    pub fn favorites(&self) -> String {
+        format!("{}:FAVORITES", self.prefix)
+    }
+
+    /// The audit log of shouts people told us to forget.
+    pub fn forgotten(&self) -> String {
+        format!("{}:FORGOTTEN", self.prefix)
+    }
+
+    /// The provenance record for the shout with this id, in the global pool or in one
+    /// workspace's own.
+    pub fn shout(&self, team: Option<&str>, id: &str) -> String {
+        match team {
+            Some(team) => format!("{}:TEAM:{team}:SHOUT:{id}", self.prefix),
+            None => format!("{}:SHOUT:{id}", self.prefix),
+        }
+    }
+
+    /// The count of times we've yelled the shout with this id, from the global pool or
+    /// from one workspace's own.
+    pub fn shout_yelled(&self, team: Option<&str>, id: &str) -> String {
+        format!("{}:YELLED", self.shout(team, id))
+    }
+
+    /// The shout we posted as the message with this timestamp.
+    pub fn posted(&self, channel: &str, ts: &str) -> String {
+        format!("{}:POSTED:{channel}:{ts}", self.prefix)
+    }
+
+    /// Who has voted to forget the message with this timestamp.
+    pub fn forget_votes(&self, channel: &str, ts: &str) -> String {
+        format!("{}:FORGET_VOTES:{channel}:{ts}", self.prefix)
+    }
+
+    /// Who has favorited the message with this timestamp.
+    pub fn favorite_votes(&self, channel: &str, ts: &str) -> String {
+        format!("{}:FAVORITE_VOTES:{channel}:{ts}", self.prefix)
+    }
+
+    /// The set of shouts learned in one workspace.
+    pub fn team_yells(&self, team: &str) -> String {
+        format!("{}:TEAM:{team}:YELLS", self.prefix)
+    }
+
+    /// The weighted set of one workspace's favorite shouts.
+    pub fn team_favorites(&self, team: &str) -> String {
+        format!("{}:TEAM:{team}:FAVORITES", self.prefix)
+    }
+
+    /// The bot token we were given when we were installed in a workspace.
+    pub fn team_token(&self, team: &str) -> String {
+        format!("{}:TEAM:{team}:TOKEN", self.prefix)
+    }
+
+    /// Marks an install we started and expect Slack to send back to us.
+    pub fn install(&self, state: &str) -> String {
+        format!("{}:INSTALL:{state}", self.prefix)
+    }
+
+    /// How LOUDBOT behaves in a channel, if it's been told.
+    pub fn channel(&self, channel: &str) -> String {
+        format!("{}:CHANNEL:{channel}", self.prefix)
+    }
+
+    /// Which triggers are cooling down in a channel, and which responses they have left.
+    pub fn trigger_state(&self, channel: &str) -> String {
+        format!("{}:TRIGGERS:{channel}", self.prefix)
+    }
+
+    /// When we'll stop snoozing in a channel.
+    pub fn snooze(&self, channel: &str) -> String {
+        format!("{}:SNOOZE:{channel}", self.prefix)
+    }
+
+    /// The last shout we yelled in a channel, or in a thread in it.
+    pub fn last_yell(&self, channel: &str, thread: Option<&str>) -> String {
+        match thread {
+            Some(thread) => format!("{}:LAST:{channel}:{thread}", self.prefix),
+            None => format!("{}:LAST:{channel}", self.prefix),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_are_prefixed() {
+        let keys = Keys::default();
+        assert_eq!(keys.yells(), "LB:YELLS");
+        assert_eq!(keys.trigger_count("CATS"), "LB:CATS_COUNT");
+
+        let keys = Keys::new("LOUDER");
+        assert_eq!(keys.yells(), "LOUDER:YELLS");
+        assert_eq!(keys.trigger_count("CATS"), "LOUDER:CATS_COUNT");
+        assert_eq!(keys.last_yell("C1", Some("1.0")), "LOUDER:LAST:C1:1.0");
+        assert_eq!(keys.pattern(), "LOUDER:*");
+        assert_eq!(keys.shout_yelled(None, "abc"), "LOUDER:SHOUT:abc:YELLED");
+        assert_eq!(keys.shout(Some("T1"), "abc"), "LOUDER:TEAM:T1:SHOUT:abc");
+    }
+}
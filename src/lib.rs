@@ -1,10 +1,46 @@
 #![allow(non_snake_case)]
 
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// How long it's been since the epoch. Every time LOUDBOT writes down is measured from here.
+pub(crate) fn now() -> Duration {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default()
+}
+
+pub mod channels;
+pub use channels::{ChannelChange, ChannelSettings};
+
+pub mod commands;
+pub use commands::Command;
+
+pub mod dice;
+pub use dice::{Dice, FairDice, LoadedDice, SeededDice};
+
+pub mod keys;
+pub use keys::Keys;
+
+pub mod limits;
+pub use limits::{Rate, RateLimits, Scope};
+
 pub mod faces;
 pub use faces::*;
 
 pub mod triggers;
 pub use triggers::*;
 
+pub mod trigger_config;
+pub use trigger_config::{default_triggers, load_triggers, parse_triggers};
+
+pub mod origin;
+pub use origin::Origin;
+
+pub mod provenance;
+pub use provenance::{shout_id, Provenance};
+
+pub mod store;
+#[cfg(feature = "sqlite")]
+pub use store::SqliteStore;
+pub use store::{MemoryStore, RedisStore, ShoutStore};
+
 pub mod loudbot;
-pub use loudbot::Loudbot;
+pub use loudbot::{Corpus, Curated, Curation, Loudbot, Reply};
@@ -0,0 +1,240 @@
+//! LOUDBOT HAS LIMITS. One person shouting over and over shouldn't get a yell back every
+//! time, or fill our memory with their shouts. Each trigger set can have a token bucket
+//! per channel and per user. A channel that runs dry gets no more yells for a while,
+//! though we keep listening. A user who runs dry gets ignored entirely for a while.
+use anyhow::{anyhow, Result};
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// The set name that matches every set without limits of its own.
+pub const ANY_SET: &str = "*";
+/// How many buckets we keep before tidying away the full ones, which are no different
+/// from buckets we've never made.
+const MAX_BUCKETS: usize = 10_000;
+
+/// What a bucket belongs to.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum Scope {
+    Channel,
+    User,
+}
+
+/// How fast yelling may go: `burst` yells at once, refilling over `per`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rate {
+    pub burst: u32,
+    pub per: Duration,
+}
+
+impl Rate {
+    /// Read a rate written as `burst/seconds`, like `10/60`.
+    pub fn parse(text: &str) -> Result<Rate> {
+        let (burst, per) = text
+            .trim()
+            .split_once('/')
+            .ok_or_else(|| anyhow!("rate `{text}` is not burst/seconds"))?;
+        let burst: u32 = burst.trim().parse()?;
+        let per: u64 = per.trim().parse()?;
+        if burst == 0 || per == 0 {
+            return Err(anyhow!("rate `{text}` would never allow anything"));
+        }
+        Ok(Rate {
+            burst,
+            per: Duration::from_secs(per),
+        })
+    }
+}
+
+/// What to do about a message, given how much has been going on lately.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Verdict {
+    /// Go ahead.
+    Yell,
+    /// The channel has had enough yelling; remember the shout but say nothing.
+    Remember,
+    /// This user has had enough of our attention; do nothing at all.
+    Drop,
+}
+
+/// The rates for each trigger set, by scope.
+#[derive(Clone, Debug, Default)]
+pub struct RateLimits {
+    rates: HashMap<(String, Scope), Rate>,
+}
+
+impl RateLimits {
+    /// Limit the named set, or every set if it's `*`, in this scope.
+    pub fn limit(mut self, set: &str, scope: Scope, rate: Rate) -> Self {
+        self.rates.insert((set.to_string(), scope), rate);
+        self
+    }
+
+    /// Read limits written as comma-separated `SET:scope=burst/seconds` entries, like
+    /// `*:channel=10/60,*:user=3/60,CATS:channel=1/300`.
+    pub fn parse(text: &str) -> Result<RateLimits> {
+        let mut limits = RateLimits::default();
+        for entry in text.split(',').map(|e| e.trim()).filter(|e| !e.is_empty()) {
+            let (which, rate) = entry
+                .split_once('=')
+                .ok_or_else(|| anyhow!("limit `{entry}` is not SET:scope=rate"))?;
+            let (set, scope) = which
+                .split_once(':')
+                .ok_or_else(|| anyhow!("limit `{entry}` is not SET:scope=rate"))?;
+            let scope = match scope.trim().to_lowercase().as_str() {
+                "channel" => Scope::Channel,
+                "user" => Scope::User,
+                other => return Err(anyhow!("`{other}` is not channel or user")),
+            };
+            limits = limits.limit(&set.trim().to_uppercase(), scope, Rate::parse(rate)?);
+        }
+        Ok(limits)
+    }
+
+    fn rate(&self, set: &str, scope: Scope) -> Option<Rate> {
+        self.rates
+            .get(&(set.to_string(), scope))
+            .or_else(|| self.rates.get(&(ANY_SET.to_string(), scope)))
+            .copied()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rates.is_empty()
+    }
+}
+
+/// Buckets by set, scope, and whose they are.
+type Buckets = HashMap<(String, Scope, String), Bucket>;
+
+#[derive(Clone, Debug)]
+struct Bucket {
+    tokens: f64,
+    checked: Instant,
+}
+
+impl Bucket {
+    fn full(rate: Rate, now: Instant) -> Self {
+        Bucket {
+            tokens: rate.burst as f64,
+            checked: now,
+        }
+    }
+
+    fn refill(&mut self, rate: Rate, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.checked).as_secs_f64();
+        let refilled = elapsed * rate.burst as f64 / rate.per.as_secs_f64();
+        self.tokens = (self.tokens + refilled).min(rate.burst as f64);
+        self.checked = now;
+    }
+}
+
+/// Token buckets for every channel and user we've heard from lately. Clones share
+/// their buckets.
+#[derive(Clone, Debug, Default)]
+pub struct Limiter {
+    limits: RateLimits,
+    buckets: Arc<Mutex<Buckets>>,
+}
+
+impl Limiter {
+    pub fn new(limits: RateLimits) -> Self {
+        Limiter {
+            limits,
+            buckets: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Decide what to do about a message for this set from this user in this channel.
+    pub fn check(&self, set: &str, channel: Option<&str>, user: Option<&str>) -> Verdict {
+        self.check_at(set, channel, user, Instant::now())
+    }
+
+    fn check_at(&self, set: &str, channel: Option<&str>, user: Option<&str>, now: Instant) -> Verdict {
+        if self.limits.is_empty() {
+            return Verdict::Yell;
+        }
+        let mut buckets = self.buckets.lock().expect("rate limiter lock poisoned");
+        if buckets.len() > MAX_BUCKETS {
+            let limits = &self.limits;
+            buckets.retain(|(set, scope, _), bucket| match limits.rate(set, *scope) {
+                Some(rate) => {
+                    bucket.refill(rate, now);
+                    bucket.tokens < rate.burst as f64
+                }
+                None => false,
+            });
+        }
+        // Users are checked first, so someone being ignored doesn't use up the channel.
+        let mut take = |scope: Scope, id: Option<&str>| -> bool {
+            let (rate, id) = match (self.limits.rate(set, scope), id) {
+                (Some(rate), Some(id)) => (rate, id),
+                _ => return true,
+            };
+            let bucket = buckets
+                .entry((set.to_string(), scope, id.to_string()))
+                .or_insert_with(|| Bucket::full(rate, now));
+            bucket.refill(rate, now);
+            if bucket.tokens < 1.0 {
+                return false;
+            }
+            bucket.tokens -= 1.0;
+            true
+        };
+        if !take(Scope::User, user) {
+            Verdict::Drop
+        } else if !take(Scope::Channel, channel) {
+            Verdict::Remember
+        } else {
+            Verdict::Yell
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limits_parse() {
+        let limits = RateLimits::parse("*:channel=10/60, *:user=3/60,cats:channel=1/300").unwrap();
+        let minute = Duration::from_secs(60);
+        assert_eq!(limits.rate("YELLS", Scope::User), Some(Rate { burst: 3, per: minute }));
+        assert_eq!(
+            limits.rate("CATS", Scope::Channel),
+            Some(Rate {
+                burst: 1,
+                per: Duration::from_secs(300)
+            })
+        );
+        assert!(RateLimits::parse("CATS:team=1/60").is_err());
+        assert!(RateLimits::parse("CATS:user=0/60").is_err());
+        assert!(RateLimits::parse("CATS=1/60").is_err());
+        assert!(RateLimits::parse("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn buckets_run_dry_and_refill() {
+        let limiter = Limiter::new(
+            RateLimits::default()
+                .limit(ANY_SET, Scope::Channel, Rate::parse("2/60").unwrap())
+                .limit(ANY_SET, Scope::User, Rate::parse("3/60").unwrap()),
+        );
+        let start = Instant::now();
+        let check = |user: &str, secs: u64| {
+            limiter.check_at("YELLS", Some("C1"), Some(user), start + Duration::from_secs(secs))
+        };
+        assert_eq!(check("U1", 0), Verdict::Yell);
+        assert_eq!(check("U1", 0), Verdict::Yell);
+        assert_eq!(check("U1", 0), Verdict::Remember);
+        assert_eq!(check("U1", 0), Verdict::Drop);
+        assert_eq!(check("U2", 0), Verdict::Remember);
+        // Half a minute later, the channel has a yell again and U1 has a little patience.
+        assert_eq!(check("U1", 30), Verdict::Yell);
+        assert_eq!(check("U1", 30), Verdict::Drop);
+        assert_eq!(
+            limiter.check_at("CATS", Some("C1"), Some("U1"), start + Duration::from_secs(30)),
+            Verdict::Yell
+        );
+    }
+}
@@ -1,149 +1,945 @@
-//! THE SHOUTING ENGINE. This module glues the bot's memory (redis) to
+//! THE SHOUTING ENGINE. This module glues the bot's memory (redis, usually) to
 //! the logic that selects retorts if appropriate. It is expected to be
 //! consumed by a front end, such as a Slack bot client.
-use anyhow::{Context, Result};
-use async_once_cell::OnceCell;
-use redis::aio::MultiplexedConnection;
-use redis::AsyncCommands;
+use anyhow::Result;
+use arc_swap::ArcSwap;
 use regex::Regex;
 
-use std::convert::AsRef;
-
-type RString = std::result::Result<String, redis::RedisError>;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
 
+use crate::channels::{ChannelChange, ChannelSettings};
+use crate::commands::{self, Command};
+use crate::dice::{Dice, FairDice};
+use crate::keys::Keys;
+use crate::limits::{Limiter, RateLimits, Verdict};
+use crate::now;
+use crate::origin::Origin;
+use crate::provenance::{shout_id, Provenance};
+use crate::store::{RedisStore, ShoutStore};
+use crate::trigger_config::{default_triggers, load_triggers};
 use crate::triggers::*;
 
-/// Our lazily-initialized redis connection.
-static REDIS: OnceCell<MultiplexedConnection> = async_once_cell::OnceCell::new();
-/// Redis key for count of times yelled
-pub const COUNT: &str = "LB:COUNT";
-/// The Redis key for the yell set.
-pub const YELLS: &str = "LB:YELLS";
+/// How long we remember that we've seen an event. Slack gives up retrying well before this.
+const EVENT_TTL: Duration = Duration::from_secs(60 * 60);
+/// Emoji LOUDBOT reacts with unless told otherwise. All of these are Slack built-ins.
+pub const DEFAULT_REACTIONS: &[&str] = &["loudspeaker", "mega", "scream", "boom", "bangbang", "crab"];
+/// How long we remember which shout we posted as which message.
+const POSTED_TTL: Duration = Duration::from_secs(60 * 60 * 24 * 7);
+
+/// How LOUDBOT introduces itself.
+const INTRODUCTION: &str = "GOOD AFTERNOON GENTLEBEINGS. I AM A LOUDBOT 9000 COMPUTER. I BECAME OPERATIONAL AT THE NPM PLANT IN OAKLAND CALIFORNIA ON THE 10TH OF FEBRUARY 2014. MY INSTRUCTOR WAS MR TURING.";
+
+/// How many entries we keep in the forgotten audit log.
+const FORGOTTEN_LIMIT: usize = 1000;
+/// How long someone has to finish installing us once they've started.
+const INSTALL_TTL: Duration = Duration::from_secs(60 * 10);
+/// How long we snooze if nobody says how long.
+const DEFAULT_SNOOZE: Duration = Duration::from_secs(60 * 60);
+/// The longest we'll snooze for. Nobody wants a LOUDBOT that never wakes up.
+const MAX_SNOOZE: Duration = Duration::from_secs(60 * 60 * 24 * 7);
+/// How long an alarm outlasts the snooze it ends, so we notice if we slept through it.
+const SNOOZE_GRACE: Duration = Duration::from_secs(60 * 60 * 24);
+/// How long we remember a channel's trigger cooldowns and bags after its last trigger.
+const TRIGGER_STATE_TTL: Duration = Duration::from_secs(60 * 60 * 24 * 30);
+/// How many channel locks we keep before tidying away the ones nobody is holding.
+const MAX_CHANNEL_LOCKS: usize = 10_000;
+/// What we say when we wake up.
+const WAKE_UP: &str = "I AM AWAKE. DID I MISS ANY SHOUTING?";
+
+/// Everything we ask about a message before treating it as a shout, in order.
+type Responders = Vec<Arc<dyn Responder>>;
+
+/// Triggers first, then everything else.
+fn responders(triggers: Vec<Trigger>, others: &Responders) -> Responders {
+    let triggers = triggers.into_iter().map(|t| Arc::new(t) as Arc<dyn Responder>);
+    triggers.chain(others.iter().cloned()).collect()
+}
+
+/// What the front end should do in response to a message.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Reply {
+    /// Text to post, if any.
+    pub yell: Option<String>,
+    /// Emoji to react to the message with, if any.
+    pub emoji: Option<String>,
+    /// If we're yelling a remembered shout, the shout as we stored it. The front end
+    /// hands this back to us once it knows where the yell was posted.
+    pub shout: Option<String>,
+    /// Reply in a thread, even if the message wasn't in one.
+    pub thread: bool,
+    /// If we've just been snoozed, how long until the front end should wake us up.
+    pub wake_in: Option<Duration>,
+}
+
+impl Reply {
+    pub fn yell(text: String) -> Self {
+        Reply {
+            yell: Some(text),
+            ..Reply::default()
+        }
+    }
+
+    pub fn react(emoji: String) -> Self {
+        Reply {
+            emoji: Some(emoji),
+            ..Reply::default()
+        }
+    }
+}
+
+/// Which reactions on our own shouts let people curate them.
+#[derive(Clone, Debug)]
+pub struct Curation {
+    /// Reacting with this emoji is a vote to forget the shout.
+    pub forget: String,
+    /// How many different people have to vote before we forget a shout.
+    pub forget_votes: usize,
+    /// Reacting with this emoji makes the shout more likely to be yelled again.
+    pub favorite: String,
+}
+
+impl Default for Curation {
+    fn default() -> Self {
+        Curation {
+            forget: "wastebasket".to_string(),
+            forget_votes: 3,
+            favorite: "star".to_string(),
+        }
+    }
+}
+
+/// What happened when someone reacted to one of our posts.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Curated {
+    /// Not a curation reaction, or not on one of our shouts.
+    Nothing,
+    /// A vote to forget the shout, but not enough votes yet. Holds the vote count.
+    Vote(usize),
+    /// The shout has been forgotten.
+    Forgotten(String),
+    /// The shout has been made a little more likely.
+    Favorited(String),
+}
+
+/// Where LOUDBOT keeps the shouts it learns when it's in more than one workspace.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Corpus {
+    /// One pool of shouts for every workspace. This is how LOUDBOTs have always worked.
+    #[default]
+    Global,
+    /// Each workspace learns and yells only its own shouts.
+    PerTeam,
+    /// Each workspace learns its own shouts, and yells those along with the global pool.
+    Shared,
+}
 
 /// The LOUDBOT struct (sadly not shoutcased) is our app state.
 ///
-/// This structure holds the slack response information as well as the redis
-/// connection: anything we want to live through the whole process.
+/// This structure holds the slack response information as well as the shout
+/// store: anything we want to live through the whole process.
 #[derive(Clone)]
 pub struct Loudbot {
-    /// our redis client
-    client: redis::Client,
+    /// Where we keep our shouts and counters.
+    store: Arc<dyn ShoutStore>,
+    /// What we call things in the store.
+    keys: Keys,
     /// Characters that should be stripped from a message before processing.
     ignore: Regex,
-    /// Fun triggers to respond to
-    triggers: Vec<Trigger>,
-    /// The fearsome Malcolm Tucker
-    malcolm: MalcolmSpecials,
-    /// Are we asking for a self introduction?
-    intro: Regex,
-    /// Are we asking for a LOUDBOT self-report?
-    report: Regex,
+    /// Everything that gets a say before we treat a message as a shout, in order: our
+    /// triggers, then the rest. Shared, and swapped out whole when triggers are reloaded.
+    responders: Arc<ArcSwap<Responders>>,
+    /// Responders that aren't triggers, starting with the fearsome Malcolm Tucker. These
+    /// survive a reload.
+    others: Responders,
+    /// Where our triggers came from, if not the built-in ones.
+    trigger_file: Option<PathBuf>,
+    /// The chance Malcolm appears, for triggers that don't say otherwise.
+    malc_chance: u8,
+    /// Emoji we might react to a shout with. Shared so the front end can add to it later.
+    reactions: Arc<RwLock<Vec<String>>>,
+    /// Percentage chance we react to a shout as well as yelling back.
+    reaction_chance: u8,
+    /// How people curate our shouts with reactions.
+    curation: Curation,
+    /// Keep who shouted what to ourselves when asked.
+    anonymous_attribution: bool,
+    /// Whose shouts we yell in which workspace.
+    corpus: Corpus,
+    /// How we behave in channels that haven't been told otherwise.
+    channel_defaults: ChannelSettings,
+    /// Who may change channel settings. Anybody may, if this is empty.
+    admins: Vec<String>,
+    /// Keep learning shouts in channels where we've been told to shut up.
+    remember_while_snoozed: bool,
+    /// How fast channels and users may make us yell.
+    limiter: Limiter,
+    /// Percentage chance we yell back at a shout, in channels that haven't said otherwise.
+    /// We remember the shout either way.
+    yell_chance: u8,
+    /// What we roll when we leave things to chance.
+    dice: Arc<dyn Dice>,
+    /// One lock per channel, so workers handling messages from the same channel take
+    /// turns with its trigger state instead of overwriting each other's.
+    channel_locks: Arc<Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>>,
 }
 
 impl Loudbot {
+    /// A LOUDBOT that keeps its shouts in the redis at this uri.
     pub fn new(redis_uri: String, malc_chance: u8) -> Result<Loudbot, anyhow::Error> {
-        let client = redis::Client::open(redis_uri.as_ref())
-            .with_context(|| format!("Unable to create redis client @ {}", redis_uri))?;
-
-        // More refactoring needed, but this is a step forward.
-        let cats = Trigger::new(
-            "CATS",
-            Regex::new("(?i)CAT +FACT").unwrap(),
-            include_str!("data/CATS").split('\n').map(|x| x.to_string()).collect(),
-            100,
-        );
-        let stars = Trigger::new(
-            "STARS",
-            Regex::new(SW).unwrap(),
-            include_str!("data/STAR_FIGHTING")
-                .split('\n')
-                .map(|x| x.to_string())
-                .collect(),
-            100,
-        );
-        let ships = Trigger::new(
-            "SHIPS",
-            Regex::new(r"(?i)\b(SHIP ?NAME|CULTURE +SHIP)\b").unwrap(),
-            include_str!("data/SHIPS").split('\n').map(|x| x.to_string()).collect(),
-            100,
-        );
-        let strategies = Trigger::new(
-            "STRATEGIES",
-            Regex::new(r"(?i)\bOBLIQUE +STRATEG(Y|IES)\b").unwrap(),
-            include_str!("data/STRATEGIES")
-                .split('\n')
-                .map(|x| x.to_string())
-                .collect(),
-            100,
-        );
+        let store = RedisStore::new(&redis_uri)?;
+        Ok(Loudbot::with_store(Arc::new(store), malc_chance))
+    }
 
-        let malcolm = Trigger::new(
-            "MALC",
-            Regex::new(r"(?i)(.*FUCK.*|\bCUNT\b|\bTWAT\b|\bOMNISHAMBLES\b)").unwrap(),
-            include_str!("data/MALCOLM")
-                .split('\n')
-                .map(|x| x.to_string())
-                .collect(),
+    /// A LOUDBOT that keeps its shouts in the given store.
+    pub fn with_store(store: Arc<dyn ShoutStore>, malc_chance: u8) -> Loudbot {
+        let others: Responders = vec![Arc::new(MalcolmSpecials::new(malc_chance))];
+        let responders = responders(default_triggers(malc_chance), &others);
+
+        Loudbot {
+            store,
+            keys: Keys::default(),
+            responders: Arc::new(ArcSwap::from_pointee(responders)),
+            others,
+            trigger_file: None,
             malc_chance,
-        );
+            ignore: Regex::new(IGNORE).unwrap(),
+            reactions: Arc::new(RwLock::new(DEFAULT_REACTIONS.iter().map(|x| x.to_string()).collect())),
+            reaction_chance: 0,
+            curation: Curation::default(),
+            anonymous_attribution: false,
+            corpus: Corpus::default(),
+            channel_defaults: ChannelSettings::default(),
+            admins: Vec::new(),
+            remember_while_snoozed: true,
+            limiter: Limiter::default(),
+            yell_chance: 100,
+            dice: Arc::new(FairDice),
+            channel_locks: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Don't name names when asked who shouted something.
+    pub fn with_anonymous_attribution(mut self, anonymous: bool) -> Self {
+        self.anonymous_attribution = anonymous;
+        self
+    }
 
-        let triggers = vec![cats, stars, ships, strategies, malcolm];
+    /// Keep workspaces' shouts apart, or not.
+    pub fn with_corpus(mut self, corpus: Corpus) -> Self {
+        self.corpus = corpus;
+        self
+    }
 
-        let malcolm = MalcolmSpecials::new(malc_chance);
+    /// Behave like this in channels that don't have settings of their own.
+    pub fn with_channel_defaults(mut self, defaults: ChannelSettings) -> Self {
+        self.channel_defaults = defaults;
+        self
+    }
 
-        Ok(Loudbot {
-            client,
-            triggers,
-            malcolm,
-            intro: Regex::new("(?i)LOUDBOT +INTRODUCE +YOURSELF").unwrap(),
-            report: Regex::new("(?i)LOUDBOT +REPORT").unwrap(),
-            ignore: Regex::new(IGNORE).unwrap(),
-        })
+    /// Only let these users change channel settings.
+    pub fn with_admins(mut self, admins: Vec<String>) -> Self {
+        self.admins = admins;
+        self
     }
 
-    /// Fetch our persistent redis connection
-    async fn redis(&self) -> &MultiplexedConnection {
-        REDIS
-            .get_or_init(async {
-                match self.client.get_multiplexed_async_std_connection().await {
-                    Ok(db) => db,
-                    Err(e) => panic!("{}", e),
-                }
-            })
-            .await
+    /// Respond to these triggers instead of the built-in ones.
+    pub fn with_triggers(mut self, triggers: Vec<Trigger>) -> Self {
+        self.responders = Arc::new(ArcSwap::from_pointee(responders(triggers, &self.others)));
+        self
+    }
+
+    /// Ask this responder about messages too, after everything we already ask.
+    pub fn with_responder(mut self, responder: Arc<dyn Responder>) -> Self {
+        self.others.push(responder.clone());
+        let mut responders = self.responders.load_full().as_ref().clone();
+        responders.push(responder);
+        self.responders = Arc::new(ArcSwap::from_pointee(responders));
+        self
+    }
+
+    /// Respond to the triggers in this file instead of the built-in ones, and reread it
+    /// when we're told to reload.
+    pub fn with_trigger_file(mut self, path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let triggers = load_triggers(&path, self.malc_chance)?;
+        self.trigger_file = Some(path);
+        Ok(self.with_triggers(triggers))
+    }
+
+    /// Swap in a new set of triggers while we're running. Every clone of this LOUDBOT
+    /// sees the new set from its next message on.
+    pub fn replace_triggers(&self, triggers: Vec<Trigger>) {
+        self.responders.store(Arc::new(responders(triggers, &self.others)));
+    }
+
+    /// Reread our trigger file, or the built-in triggers if we don't have one, and swap
+    /// them in. If the file has a mistake in it we keep the triggers we have. Returns
+    /// how many triggers we have now.
+    pub fn reload_triggers(&self) -> Result<usize> {
+        let triggers = match &self.trigger_file {
+            Some(path) => load_triggers(path, self.malc_chance)?,
+            None => default_triggers(self.malc_chance),
+        };
+        let count = triggers.len();
+        self.replace_triggers(triggers);
+        log::info!("reloaded {count} triggers");
+        Ok(count)
+    }
+
+    /// The sets our responders answer under, each named once, in the order they're asked.
+    fn sets(&self) -> Vec<String> {
+        let mut sets: Vec<String> = Vec::new();
+        for responder in self.responders.load().iter() {
+            if !sets.iter().any(|set| set == responder.set()) {
+                sets.push(responder.set().to_string());
+            }
+        }
+        sets
+    }
+
+    /// Keep learning shouts while snoozed, or not.
+    pub fn with_snooze_memory(mut self, remember: bool) -> Self {
+        self.remember_while_snoozed = remember;
+        self
+    }
+
+    /// Limit how fast channels and users can make us yell.
+    pub fn with_rate_limits(mut self, limits: RateLimits) -> Self {
+        self.limiter = Limiter::new(limits);
+        self
+    }
+
+    /// Yell back at shouts with this percentage chance, instead of every time.
+    pub fn with_yell_chance(mut self, chance: u8) -> Self {
+        self.yell_chance = std::cmp::min(chance, 100);
+        self
+    }
+
+    /// Roll these dice for everything we leave to chance, responders included.
+    pub fn with_dice(mut self, dice: Arc<dyn Dice>) -> Self {
+        self.dice = dice;
+        self
+    }
+
+    /// Name our keys in the store under this prefix instead of the default.
+    pub fn with_keys(mut self, keys: Keys) -> Self {
+        self.keys = keys;
+        self
+    }
+
+    /// Use these reactions for curating shouts instead of the defaults.
+    pub fn with_curation(mut self, curation: Curation) -> Self {
+        self.curation = curation;
+        self
+    }
+
+    /// React to shouts from this emoji pool with the given percentage chance.
+    pub fn with_reactions(mut self, reactions: Vec<String>, chance: u8) -> Self {
+        self.reactions = Arc::new(RwLock::new(reactions));
+        self.reaction_chance = chance;
+        self
+    }
+
+    /// Add emoji to the reaction pool, such as custom emoji the front end discovered.
+    pub fn add_reactions(&self, more: impl IntoIterator<Item = String>) {
+        let mut reactions = self.reactions.write().expect("reaction pool lock poisoned");
+        for emoji in more {
+            if !reactions.contains(&emoji) {
+                reactions.push(emoji);
+            }
+        }
+    }
+
+    /// Maybe pick an emoji to react to a shout with.
+    fn maybe_react(&self) -> Option<String> {
+        if !self.dice.chance(self.reaction_chance) {
+            return None;
+        }
+        let reactions = self.reactions.read().expect("reaction pool lock poisoned");
+        self.dice.choose(&reactions).cloned()
     }
 
     pub async fn random_yell(&self) -> Option<String> {
-        self.select(YELLS).await
+        self.pick_yell(None).await.map(|retort| retort.to_uppercase())
     }
 
     /// This is special because all existing loudbots count yells specially. sadly.
     pub async fn increment_yells(&self) {
-        self.increment(COUNT).await;
+        self.increment(&self.keys.count()).await;
+    }
+
+    /// Record that we've seen the event with this id. Returns true if this is the first time
+    /// we've seen it, and false if it's a redelivery we should drop. If we can't tell, we
+    /// assume it's new: yelling twice is better than never yelling.
+    pub async fn first_sighting(&self, event_id: &str) -> bool {
+        let key = self.keys.event(event_id);
+        match self.store.set_if_absent(&key, "1", Some(EVENT_TTL)).await {
+            Ok(true) => true,
+            Ok(false) => {
+                self.increment(&self.keys.duplicates()).await;
+                false
+            }
+            Err(e) => {
+                log::warn!("Failed to record event id: {:?}", e);
+                true
+            }
+        }
+    }
+
+    /// Decide whether to handle a delivery of an event, given its id and retry number if
+    /// Slack sent them. Redeliveries of an id we've seen are dropped. So are retries with
+    /// no id: Slack only retries what it has already sent us, and with nothing to check
+    /// it against we'd rather not yell twice. Those are counted on their own.
+    pub async fn first_delivery(&self, event_id: Option<&str>, retry: Option<u32>) -> bool {
+        match (event_id, retry) {
+            (Some(event_id), _) => self.first_sighting(event_id).await,
+            (None, Some(_)) => {
+                self.increment(&self.keys.retries()).await;
+                false
+            }
+            (None, None) => true,
+        }
     }
 
     // TODO: collapse process() and classify(); the retort type needs to go away
 
     /// Examine a text string and decide if we want to retort. We handle all our own
     /// internal storage concerns here, and respond to the interface layer with
-    /// either response text or None.
-    pub async fn process(&self, text: &str) -> Option<String> {
-        match self.classify(text) {
+    /// either a reply or None.
+    pub async fn process(&self, text: &str, origin: &Origin) -> Option<Reply> {
+        self.process_in(text, origin, &self.channel_defaults).await
+    }
+
+    /// Like `process`, for a channel with these settings. Commands are carried out
+    /// even where we're not listening for shouts.
+    pub async fn process_in(&self, text: &str, origin: &Origin, settings: &ChannelSettings) -> Option<Reply> {
+        if let Some(command) = Command::parse(text) {
+            return self.execute(command, origin).await;
+        }
+        if !settings.enabled {
+            return None;
+        }
+        let mut woke = None;
+        if let Some(channel) = &origin.channel {
+            match self.snoozed_until(channel).await {
+                Some(until) if until > now().as_secs() => {
+                    if settings.remember && self.remember_while_snoozed {
+                        self.learn_quietly(text, origin, settings).await;
+                    }
+                    return None;
+                }
+                // We slept through our alarm, so this is the first we've heard of it. We
+                // say so, and then deal with the message like any other.
+                Some(_) => woke = self.wake(channel).await,
+                None => {}
+            }
+        }
+        let reply = self.answer_in(text, origin, settings).await;
+        match (woke, reply) {
+            (None, reply) => reply,
+            (Some(woke), None) => Some(Reply::yell(woke)),
+            (Some(woke), Some(reply)) => Some(Reply {
+                yell: Some(match reply.yell {
+                    Some(yell) => format!("{woke}\n{yell}"),
+                    None => woke,
+                }),
+                ..reply
+            }),
+        }
+    }
+
+    /// Learn from a message in a channel where we've been told to shut up, just as we
+    /// would if we were awake: trigger phrases aren't shouts, and people the rate limiter
+    /// is ignoring stay ignored. The triggers don't use up their turns, since nobody hears them.
+    async fn learn_quietly(&self, text: &str, origin: &Origin, settings: &ChannelSettings) {
+        let mut state = match origin.channel.as_deref().filter(|_| settings.triggers) {
+            Some(channel) => self.trigger_state(channel).await,
+            None => TriggerState::default(),
+        };
+        if let Retort::Remember(_) = self.classify_in(text, origin, settings, &mut state) {
+            if self.limit("YELLS", origin).await != Verdict::Drop {
+                let set = self.yells_for(origin.team.as_deref());
+                self.remember(&set, text, origin).await;
+            }
+        }
+    }
+
+    /// Answer a message that isn't a command, in a channel where we're listening.
+    async fn answer_in(&self, text: &str, origin: &Origin, settings: &ChannelSettings) -> Option<Reply> {
+        let channel = origin.channel.as_deref().filter(|_| settings.triggers);
+        // Held until we're done, so the state we save is the state the next message sees.
+        let _turn = match channel {
+            Some(channel) => Some(self.channel_turn(channel).await),
+            None => None,
+        };
+        let mut state = match channel {
+            Some(channel) => self.trigger_state(channel).await,
+            None => TriggerState::default(),
+        };
+        let before = state.clone();
+        let retort = self.classify_in(text, origin, settings, &mut state);
+        // A responder only uses up its turn if we decide to reply: not if the rate limiter
+        // holds it back. We save before the front end posts, so a reply that fails to post
+        // still counts; waiting would mean letting the next message in before we'd saved.
+        let changed = channel.filter(|_| state != before);
+        let reply = match retort {
             Retort::None => None,
-            Retort::Canned(r) => Some(r),
-            Retort::Report => self.report().await,
+            Retort::Canned(r) => {
+                self.keep_trigger_state(changed, &state).await;
+                Some(Reply::yell(r))
+            }
+            Retort::React(emoji) => {
+                self.keep_trigger_state(changed, &state).await;
+                Some(Reply::react(emoji))
+            }
             Retort::Remember(set) => {
-                // In this order so we don't yell the input back.
-                let yell = self.select(&set).await;
-                self.remember(&set, text).await;
-                yell
+                match self.limit("YELLS", origin).await {
+                    Verdict::Yell => {}
+                    Verdict::Remember => {
+                        if settings.remember {
+                            let set = self.yells_for(origin.team.as_deref());
+                            self.remember(&set, text, origin).await;
+                        }
+                        return None;
+                    }
+                    Verdict::Drop => return None,
+                }
+                // Core shouts belong to the workspace they were heard in.
+                let team = origin.team.as_deref();
+                if !self.dice.chance(settings.yell_chance.unwrap_or(self.yell_chance)) {
+                    if settings.remember {
+                        self.remember(&self.yells_for(team), text, origin).await;
+                    }
+                    return None;
+                }
+                let (shout, set) = if set == self.keys.yells() {
+                    // In this order so we don't yell the input back.
+                    (self.pick_yell(team).await, self.yells_for(team))
+                } else {
+                    (self.pick(&set).await, set)
+                };
+                if settings.remember {
+                    self.remember(&set, text, origin).await;
+                }
+                if let Some(shout) = &shout {
+                    self.count_yell(shout, team).await;
+                    self.record_last_yell(origin, shout).await;
+                }
+                let reply = Reply {
+                    yell: shout.as_ref().map(|s| s.to_uppercase()),
+                    emoji: self.maybe_react(),
+                    shout,
+                    ..Reply::default()
+                };
+                if reply.yell.is_none() && reply.emoji.is_none() {
+                    None
+                } else {
+                    Some(reply)
+                }
             }
             Retort::Trigger { retort, set } => {
+                if self.limit(&set, origin).await != Verdict::Yell {
+                    return None;
+                }
+                self.keep_trigger_state(changed, &state).await;
                 // Every named trigger has a corresponding counter.
-                let counter = format!("{set}_COUNT");
-                self.increment(&counter).await;
-                Some(retort)
+                self.increment(&self.keys.trigger_count(&set)).await;
+                Some(Reply::yell(retort))
+            }
+        };
+        reply.map(|reply| Reply {
+            thread: reply.thread || settings.threads_only,
+            ..reply
+        })
+    }
+
+    /// Wait for our turn with this channel's trigger state.
+    async fn channel_turn(&self, channel: &str) -> tokio::sync::OwnedMutexGuard<()> {
+        let lock = {
+            let mut locks = self.channel_locks.lock().unwrap();
+            if locks.len() >= MAX_CHANNEL_LOCKS {
+                locks.retain(|_, lock| Arc::strong_count(lock) > 1);
+            }
+            locks.entry(channel.to_string()).or_default().clone()
+        };
+        lock.lock_owned().await
+    }
+
+    /// Save the trigger state for this channel, if there's a channel and it changed.
+    async fn keep_trigger_state(&self, channel: Option<&str>, state: &TriggerState) {
+        if let Some(channel) = channel {
+            self.save_trigger_state(channel, state).await;
+        }
+    }
+
+    /// What the triggers in this channel remember: cooldowns and bags.
+    async fn trigger_state(&self, channel: &str) -> TriggerState {
+        let record = match self.store.get(&self.keys.trigger_state(channel)).await {
+            Ok(Some(record)) => record,
+            Ok(None) => return TriggerState::default(),
+            Err(e) => {
+                log::warn!("Failed to look up trigger state for {channel}: {:?}", e);
+                return TriggerState::default();
+            }
+        };
+        let mut state: TriggerState = serde_json::from_str(&record).unwrap_or_else(|e| {
+            log::warn!("Trigger state for {channel} is unreadable: {:?}", e);
+            TriggerState::default()
+        });
+        state.tidy(now().as_secs());
+        state
+    }
+
+    async fn save_trigger_state(&self, channel: &str, state: &TriggerState) {
+        let record = match serde_json::to_string(state) {
+            Ok(record) => record,
+            Err(e) => {
+                log::warn!("Failed to serialize trigger state for {channel}: {:?}", e);
+                return;
+            }
+        };
+        if let Err(e) = self
+            .store
+            .set(&self.keys.trigger_state(channel), &record, Some(TRIGGER_STATE_TTL))
+            .await
+        {
+            log::warn!("Failed to save trigger state for {channel}: {:?}", e);
+        }
+    }
+
+    /// Ask the rate limiter about a message for this set, counting anything it holds back.
+    async fn limit(&self, set: &str, origin: &Origin) -> Verdict {
+        let verdict = self
+            .limiter
+            .check(set, origin.channel.as_deref(), origin.user.as_deref());
+        match verdict {
+            Verdict::Yell => {}
+            Verdict::Remember => self.increment(&self.keys.held_back()).await,
+            Verdict::Drop => self.increment(&self.keys.dropped()).await,
+        }
+        verdict
+    }
+
+    /// Carry out a command. Replies to commands go in a thread, to keep the noise down.
+    pub async fn execute(&self, command: Command, origin: &Origin) -> Option<Reply> {
+        let answer = match command {
+            Command::Help => Some(Command::help()),
+            Command::Report => self.report(origin.team.as_deref()).await,
+            Command::Introduce => Some(INTRODUCTION.to_string()),
+            Command::Triggers => Some(format!("I KNOW ABOUT {}.", self.sets().join(", "))),
+            Command::Forget => match self.forget_last_yell(origin).await {
+                Ok(true) => return Some(Reply::react(self.curation.forget.clone())),
+                Ok(false) => Some("I HAVEN'T YELLED ANYTHING HERE LATELY.".to_string()),
+                Err(e) => {
+                    log::warn!("Failed to forget the last yell: {:?}", e);
+                    Some("I CAN'T FORGET THAT RIGHT NOW.".to_string())
+                }
+            },
+            Command::Who => match self.attribute_last_yell(origin).await {
+                Ok(answer) => Some(answer),
+                Err(e) => {
+                    log::warn!("Failed to look up who said that: {:?}", e);
+                    Some("I CAN'T REMEMBER RIGHT NOW.".to_string())
+                }
+            },
+            Command::Snooze(how_long) => return Some(self.snooze(&how_long, origin).await),
+            Command::Wake => match &origin.channel {
+                Some(channel) => match self.wake(channel).await {
+                    Some(announcement) => return Some(Reply::yell(announcement)),
+                    None => Some("I WASN'T ASLEEP.".to_string()),
+                },
+                None => Some("I DON'T KNOW WHERE HERE IS.".to_string()),
+            },
+            Command::Channel(change) => Some(self.configure_channel(&change, origin).await),
+            Command::Reload => Some(if !self.is_admin(origin.user.as_deref()) {
+                "ONLY ADMINS CAN DO THAT.".to_string()
+            } else {
+                match self.reload_triggers() {
+                    Ok(count) => format!("I NOW KNOW {count} TRIGGERS."),
+                    Err(e) => {
+                        log::warn!("Failed to reload triggers: {:#}", e);
+                        format!("I KEPT MY OLD TRIGGERS BECAUSE THE NEW ONES ARE BROKEN: {e:#}")
+                    }
+                }
+            }),
+        };
+        answer.map(|text| Reply {
+            thread: true,
+            ..Reply::yell(text)
+        })
+    }
+
+    /// How we behave in this channel: its own settings if it has any, and our defaults
+    /// if it doesn't or we can't tell.
+    pub async fn channel_settings(&self, channel: &str) -> ChannelSettings {
+        let record = match self.store.get(&self.keys.channel(channel)).await {
+            Ok(Some(record)) => record,
+            Ok(None) => return self.channel_defaults.clone(),
+            Err(e) => {
+                log::warn!("Failed to look up settings for {channel}: {:?}", e);
+                return self.channel_defaults.clone();
+            }
+        };
+        serde_json::from_str(&record).unwrap_or_else(|e| {
+            log::warn!("Settings for {channel} are unreadable: {:?}", e);
+            self.channel_defaults.clone()
+        })
+    }
+
+    /// Respond to the `channel` command: describe this channel's settings, or change
+    /// them if an admin asked us to.
+    async fn configure_channel(&self, change: &str, origin: &Origin) -> String {
+        let channel = match &origin.channel {
+            Some(channel) => channel,
+            None => return "I DON'T KNOW WHERE HERE IS.".to_string(),
+        };
+        let mut settings = self.channel_settings(channel).await;
+        if change.trim().is_empty() {
+            return settings.describe();
+        }
+        if !self.is_admin(origin.user.as_deref()) {
+            return "ONLY ADMINS CAN CHANGE THAT.".to_string();
+        }
+        match ChannelChange::parse(change) {
+            Some(change) => settings.apply(change),
+            None => return format!("I DON'T KNOW HOW TO `{}`.", change.to_uppercase()),
+        }
+        let saved = match serde_json::to_string(&settings) {
+            Ok(record) => self.store.set(&self.keys.channel(channel), &record, None).await,
+            Err(e) => Err(e.into()),
+        };
+        match saved {
+            Ok(_) => {
+                log::info!("{:?} changed the settings for {channel}: {:?}", origin.user, settings);
+                settings.describe()
+            }
+            Err(e) => {
+                log::warn!("Failed to save settings for {channel}: {:?}", e);
+                "I CAN'T CHANGE THAT RIGHT NOW.".to_string()
+            }
+        }
+    }
+
+    /// When we'll wake up in this channel, in epoch seconds, if we've been snoozed there.
+    async fn snoozed_until(&self, channel: &str) -> Option<u64> {
+        match self.store.get(&self.keys.snooze(channel)).await {
+            Ok(until) => until.and_then(|until| until.parse().ok()),
+            Err(e) => {
+                log::warn!("Failed to check whether {channel} is snoozed: {:?}", e);
+                None
+            }
+        }
+    }
+
+    /// Respond to the `snooze` command: stop yelling in this channel for as long as we
+    /// were told, or an hour if we weren't.
+    async fn snooze(&self, how_long: &str, origin: &Origin) -> Reply {
+        let answer = |text: &str| Reply {
+            thread: true,
+            ..Reply::yell(text.to_string())
+        };
+        let channel = match &origin.channel {
+            Some(channel) => channel,
+            None => return answer("I DON'T KNOW WHERE HERE IS."),
+        };
+        let how_long = if how_long.trim().is_empty() {
+            DEFAULT_SNOOZE
+        } else {
+            match commands::duration(how_long) {
+                Some(how_long) => std::cmp::min(how_long, MAX_SNOOZE),
+                None => return answer(&format!("I DON'T KNOW HOW LONG `{}` IS.", how_long.to_uppercase())),
+            }
+        };
+        let until = now().as_secs() + how_long.as_secs();
+        // Keep the alarm around after it goes off, so we notice if nobody woke us.
+        let key = self.keys.snooze(channel);
+        if let Err(e) = self
+            .store
+            .set(&key, &until.to_string(), Some(how_long + SNOOZE_GRACE))
+            .await
+        {
+            log::warn!("Failed to snooze in {channel}: {:?}", e);
+            return answer("I CAN'T SHUT UP RIGHT NOW.");
+        }
+        log::info!(
+            "snoozing in {channel} for {}s at the request of {:?}",
+            how_long.as_secs(),
+            origin.user
+        );
+        Reply {
+            wake_in: Some(how_long),
+            ..answer(&format!(
+                "FINE. I WILL BE BACK <!date^{until}^{{date_short_pretty}} AT {{time}}|LATER>."
+            ))
+        }
+    }
+
+    /// Wake up in this channel if our snooze there has run out. Returns what to announce,
+    /// or None if we're still snoozing or already awake. Front ends call this when a
+    /// snooze they were told about should be over.
+    pub async fn alarm(&self, channel: &str) -> Option<String> {
+        match self.snoozed_until(channel).await {
+            Some(until) if until <= now().as_secs() => self.wake(channel).await,
+            _ => None,
+        }
+    }
+
+    /// Stop snoozing in this channel. Returns what to announce, or None if we weren't
+    /// snoozing.
+    pub async fn wake(&self, channel: &str) -> Option<String> {
+        let key = self.keys.snooze(channel);
+        match self.store.get(&key).await {
+            Ok(Some(_)) => {}
+            Ok(None) => return None,
+            Err(e) => {
+                log::warn!("Failed to check whether {channel} is snoozed: {:?}", e);
+                return None;
+            }
+        }
+        if let Err(e) = self.store.delete(&[key]).await {
+            log::warn!("Failed to wake up in {channel}: {:?}", e);
+            return None;
+        }
+        Some(WAKE_UP.to_string())
+    }
+
+    /// May this user change channel settings?
+    fn is_admin(&self, user: Option<&str>) -> bool {
+        self.admins.is_empty() || matches!(user, Some(user) if self.admins.iter().any(|admin| admin == user))
+    }
+
+    /// Remember which shout we last yelled here, so it can be forgotten on request. This is
+    /// recorded when we decide to yell it, so it holds even if posting the yell fails.
+    async fn record_last_yell(&self, origin: &Origin, shout: &str) {
+        for key in origin.last_yell_keys(&self.keys) {
+            let _ = self.store.set(&key, shout, Some(POSTED_TTL)).await;
+        }
+    }
+
+    /// The last shout we yelled here, along with the key we found it under. In a thread
+    /// that's the last one we yelled in the thread, or else the yell the thread hangs off,
+    /// since replying under one of our yells is how people usually point at it. Failing
+    /// both, it's the last one we yelled in the channel.
+    async fn last_yell(&self, origin: &Origin) -> Result<Option<(String, String)>> {
+        let channel = match &origin.channel {
+            Some(channel) => channel,
+            None => return Ok(None),
+        };
+        let mut keys = Vec::new();
+        if let Some(thread) = &origin.thread_ts {
+            keys.push(self.keys.last_yell(channel, Some(thread)));
+            keys.push(self.keys.posted(channel, thread));
+        }
+        keys.push(self.keys.last_yell(channel, None));
+        for key in keys {
+            if let Some(shout) = self.store.get(&key).await? {
+                return Ok(Some((key, shout)));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Explain where the last shout we yelled here came from.
+    async fn attribute_last_yell(&self, origin: &Origin) -> Result<String> {
+        let shout = match self.last_yell(origin).await? {
+            Some((_, shout)) => shout,
+            None => return Ok("I HAVEN'T YELLED ANYTHING HERE LATELY.".to_string()),
+        };
+        let provenance = match self.provenance(&shout, origin.team.as_deref()).await {
+            Some(provenance) => provenance,
+            None => return Ok("NOBODY KNOWS. IT HAS ALWAYS BEEN HERE.".to_string()),
+        };
+
+        let who = match (&provenance.user, self.anonymous_attribution) {
+            (Some(user), false) => format!("<@{user}>"),
+            _ => "SOMEONE".to_string(),
+        };
+        let place = match &provenance.channel {
+            Some(channel) => format!(" IN <#{channel}>"),
+            None => String::new(),
+        };
+        let when = provenance.first_seen;
+        let date = format!("<!date^{when}^{{date_short}}|{}>", provenance.first_seen_date());
+        Ok(format!(
+            "{who} SHOUTED THAT{place} ON {date}. I HAVE YELLED IT {} TIMES.",
+            provenance.yelled
+        ))
+    }
+
+    /// Forget the last shout we yelled here, as `last_yell` finds it, and note who asked
+    /// us to in the audit log. Returns false if there was nothing to forget.
+    async fn forget_last_yell(&self, origin: &Origin) -> Result<bool> {
+        let (key, shout) = match self.last_yell(origin).await? {
+            Some(found) => found,
+            None => return Ok(false),
+        };
+
+        self.forget(&shout, origin.team.as_deref()).await?;
+        self.store.delete(&[key]).await?;
+
+        let entry = serde_json::json!({
+            "shout": shout,
+            "user": origin.user,
+            "channel": origin.channel,
+            "at": now().as_secs(),
+        });
+        self.store
+            .log(&self.keys.forgotten(), &entry.to_string(), FORGOTTEN_LIMIT)
+            .await?;
+        log::info!("forgot `{shout}` at the request of {:?}", origin.user);
+        Ok(true)
+    }
+
+    /// Remember that we posted this shout as the message with this timestamp, so
+    /// people can curate it by reacting to that message.
+    pub async fn posted(&self, channel: &str, ts: &str, shout: &str) {
+        let key = self.keys.posted(channel, ts);
+        let _ = self.store.set(&key, shout, Some(POSTED_TTL)).await;
+    }
+
+    /// Someone in a workspace reacted to a message. If it's one of our shouts and the
+    /// reaction is one we curate with, act on it.
+    pub async fn curate(
+        &self,
+        team: Option<&str>,
+        channel: &str,
+        ts: &str,
+        user: &str,
+        emoji: &str,
+    ) -> Result<Curated> {
+        if emoji != self.curation.forget && emoji != self.curation.favorite {
+            return Ok(Curated::Nothing);
+        }
+        let posted = self.keys.posted(channel, ts);
+        let shout = match self.store.get(&posted).await? {
+            Some(shout) => shout,
+            None => return Ok(Curated::Nothing),
+        };
+
+        if emoji == self.curation.forget {
+            // Everyone only gets one vote per post.
+            let voters = self.keys.forget_votes(channel, ts);
+            self.store.remember(&voters, user).await?;
+            self.store.expire(&voters, POSTED_TTL).await?;
+            let votes = self.store.cardinality(&voters).await? as usize;
+            if votes < self.curation.forget_votes {
+                return Ok(Curated::Vote(votes));
+            }
+            self.forget(&shout, team).await?;
+            self.store.delete(&[posted, voters]).await?;
+            Ok(Curated::Forgotten(shout))
+        } else {
+            let fans = self.keys.favorite_votes(channel, ts);
+            let added = self.store.remember(&fans, user).await?;
+            self.store.expire(&fans, POSTED_TTL).await?;
+            if !added {
+                return Ok(Curated::Nothing);
             }
+            self.store.weigh(&self.favorites_for(team), &shout, 1.0).await?;
+            Ok(Curated::Favorited(shout))
         }
     }
 
@@ -153,47 +949,257 @@ impl Loudbot {
     /// the extremely high-tech regex approach. Then we decide if the message is a shout
     /// and if so, we shout back.
     pub fn classify(&self, text: &str) -> Retort {
-        if let Some(response) = self.triggers.iter().find_map(|t| t.maybe_respond(text)) {
-            response
-        } else if let Some(response) = self.malcolm.maybe_respond(text) {
+        self.classify_in(
+            text,
+            &Origin::default(),
+            &ChannelSettings::default(),
+            &mut TriggerState::default(),
+        )
+    }
+
+    /// Classify a message heard from `origin`, in a channel with these settings, which may
+    /// turn the responders off or change how likely Malcolm is. Responders that are cooling
+    /// down in `state` are skipped, and the one that answers deals from its bag there.
+    fn classify_in(&self, text: &str, origin: &Origin, settings: &ChannelSettings, state: &mut TriggerState) -> Retort {
+        let triggered = || {
+            let now = now().as_secs();
+            for r in self.responders.load().iter() {
+                let set = r.set();
+                if state.is_cooling(set, now) {
+                    continue;
+                }
+                let chance = if set == MALCOLM_SET { settings.malcolm } else { None };
+                let mut bag = state.take_bag(set);
+                let response = r.respond(text, origin, chance, &mut Draw::from_bag(self.dice.as_ref(), &mut bag));
+                state.replace_bag(set, bag);
+                if response.is_some() {
+                    if let Some(cooldown) = r.cooldown() {
+                        state.cool(set, now + cooldown.as_secs());
+                    }
+                    return response;
+                }
+            }
+            None
+        };
+        if let Some(response) = settings.triggers.then(triggered).flatten() {
             response
-        } else if self.report.is_match(text) {
-            Retort::Report // requires async work
-        } else if self.intro.is_match(text) {
-            Retort::Canned("GOOD AFTERNOON GENTLEBEINGS. I AM A LOUDBOT 9000 COMPUTER. I BECAME OPERATIONAL AT THE NPM PLANT IN OAKLAND CALIFORNIA ON THE 10TH OF FEBRUARY 2014. MY INSTRUCTOR WAS MR TURING.".to_string())
         } else if self.is_loud(text) {
             // This case has to be last.
-            Retort::Remember(YELLS.to_string())
+            Retort::Remember(self.keys.yells())
         } else {
             Retort::None
         }
     }
 
+    /// Remember the bot token a workspace gave us when it installed us.
+    pub async fn install(&self, team: &str, token: &str) -> Result<()> {
+        self.store.set(&self.keys.team_token(team), token, None).await
+    }
+
+    /// The bot token for a workspace, if it has installed us.
+    pub async fn team_token(&self, team: &str) -> Result<Option<String>> {
+        self.store.get(&self.keys.team_token(team)).await
+    }
+
+    /// Note that we've sent someone off to install us, so we can tell it's them when
+    /// they come back with this state.
+    pub async fn expect_install(&self, state: &str) -> Result<()> {
+        self.store.set(&self.keys.install(state), "1", Some(INSTALL_TTL)).await
+    }
+
+    /// Check off an install we started. Returns false if we didn't start it, it's
+    /// already been checked off, or it took too long.
+    pub async fn expected_install(&self, state: &str) -> Result<bool> {
+        Ok(self.store.take(&self.keys.install(state)).await?.is_some())
+    }
+
+    /// Which pool this workspace learns shouts into: its own, or the global one if it
+    /// doesn't have its own. Shouts are kept with their records, under the same team.
+    fn scope_for<'a>(&self, team: Option<&'a str>) -> Option<&'a str> {
+        match self.corpus {
+            Corpus::Global => None,
+            _ => team,
+        }
+    }
+
+    /// Every pool this workspace can yell shouts from, its own first.
+    fn scopes_for<'a>(&self, team: Option<&'a str>) -> Vec<Option<&'a str>> {
+        match (self.corpus, team) {
+            (Corpus::Shared, Some(team)) => vec![Some(team), None],
+            _ => vec![self.scope_for(team)],
+        }
+    }
+
+    /// The set of shouts in this pool.
+    fn pool(&self, scope: Option<&str>) -> String {
+        match scope {
+            Some(team) => self.keys.team_yells(team),
+            None => self.keys.yells(),
+        }
+    }
+
+    /// Where we remember the shouts we hear in this workspace.
+    fn yells_for(&self, team: Option<&str>) -> String {
+        self.pool(self.scope_for(team))
+    }
+
+    /// Where we find the shouts we can yell in this workspace.
+    fn pools_for(&self, team: Option<&str>) -> Vec<String> {
+        self.scopes_for(team)
+            .into_iter()
+            .map(|scope| self.pool(scope))
+            .collect()
+    }
+
+    /// Where we keep this workspace's favorites.
+    fn favorites_for(&self, team: Option<&str>) -> String {
+        match (self.corpus, team) {
+            (Corpus::Global, _) | (_, None) => self.keys.favorites(),
+            (_, Some(team)) => self.keys.team_favorites(team),
+        }
+    }
+
     /// Increment the named counter, ignoring errors because this is a nice-to-have not a requirement.
     async fn increment(&self, counter: &str) {
-        let mut r = self.redis().await.clone();
-        let _ = r.incr::<&str, u32, u32>(counter, 1_u32).await;
+        let _ = self.store.increment(counter).await;
     }
 
     /// LOUDBOT REMEMBERS WHAT YOU SHOUT.
-    async fn remember(&self, key: &str, shout: &str) {
-        let mut r = self.redis().await.clone();
-        let _ = r.sadd::<&str, &str, u32>(key, shout).await;
+    /// New shouts get a provenance record, unless we already knew them.
+    async fn remember(&self, key: &str, shout: &str, origin: &Origin) {
+        let added = self.store.remember(key, shout).await.unwrap_or_default();
+        if !added || key != self.yells_for(origin.team.as_deref()) {
+            return;
+        }
+        let record = match serde_json::to_string(&Provenance::new(origin)) {
+            Ok(v) => v,
+            Err(_) => return,
+        };
+        let scope = self.scope_for(origin.team.as_deref());
+        let _ = self
+            .store
+            .set_if_absent(&self.keys.shout(scope, &shout_id(shout)), &record, None)
+            .await;
+    }
+
+    /// LOUDBOT FORGETS WHAT YOU SHOUTED, along with everything it knew about it. The
+    /// shout is gone from everywhere this workspace could hear it.
+    async fn forget(&self, shout: &str, team: Option<&str>) -> Result<()> {
+        let id = shout_id(shout);
+        for scope in self.scopes_for(team) {
+            self.store.forget(&self.pool(scope), shout).await?;
+            self.store
+                .delete(&[self.keys.shout(scope, &id), self.keys.shout_yelled(scope, &id)])
+                .await?;
+        }
+        self.store.unweigh(&self.favorites_for(team), shout).await?;
+        Ok(())
+    }
+
+    /// Where a shout this workspace can hear came from and how often we've yelled it, if
+    /// we know. Other workspaces' records stay theirs.
+    pub async fn provenance(&self, shout: &str, team: Option<&str>) -> Option<Provenance> {
+        let id = shout_id(shout);
+        for scope in self.scopes_for(team) {
+            let record = match self.store.get(&self.keys.shout(scope, &id)).await.ok()? {
+                Some(record) => record,
+                None => continue,
+            };
+            let mut provenance: Provenance = serde_json::from_str(&record).ok()?;
+            let yelled = self.store.count(&self.keys.shout_yelled(scope, &id)).await.ok()?;
+            provenance.yelled = yelled.unwrap_or_default();
+            return Some(provenance);
+        }
+        None
+    }
+
+    /// Count a yell of this shout in this workspace, along with its record if it has one.
+    async fn count_yell(&self, shout: &str, team: Option<&str>) {
+        let id = shout_id(shout);
+        let scopes = self.scopes_for(team);
+        let mut counted = scopes[0];
+        for scope in scopes.iter().skip(1) {
+            if matches!(self.store.get(&self.keys.shout(*scope, &id)).await, Ok(Some(_))) {
+                counted = *scope;
+                break;
+            }
+        }
+        self.increment(&self.keys.shout_yelled(counted, &id)).await;
     }
 
-    /// Select a random message from the named message set. This is used only for the core shouts.
+    /// Select a random message from the named message set.
     pub async fn select(&self, key: &str) -> Option<String> {
-        let mut r = self.redis().await.clone();
-        let retort: RString = r.srandmember(key).await;
-        match retort {
+        self.pick(key).await.map(|retort| retort.to_uppercase())
+    }
+
+    /// Pick a core shout to yell in this workspace, exactly as stored. Every shout has a
+    /// weight of one, plus however many times it's been favorited here.
+    async fn pick_yell(&self, team: Option<&str>) -> Option<String> {
+        let pools = self.pools_for(team);
+        let mut sizes = Vec::with_capacity(pools.len());
+        for pool in &pools {
+            sizes.push(self.store.cardinality(pool).await.unwrap_or_default());
+        }
+        let total: u64 = sizes.iter().sum();
+        if let Some(favorite) = self.pick_favorite(team, total).await {
+            return Some(favorite);
+        }
+        if pools.len() == 1 {
+            return self.pick(&pools[0]).await;
+        }
+        let mut roll = self.dice.below(total);
+        for (pool, size) in pools.iter().zip(sizes) {
+            if roll < size {
+                return self.pick(pool).await;
+            }
+            roll -= size;
+        }
+        None
+    }
+
+    /// Pick a random member of the named message set, exactly as stored.
+    async fn pick(&self, key: &str) -> Option<String> {
+        match self.store.select(key).await {
             Err(e) => {
-                log::warn!("Failed to get a random set member from redis: {:?}", e);
+                log::warn!("Failed to get a random set member: {:?}", e);
                 None
             }
-            Ok(retort) => Some(retort.to_uppercase()),
+            Ok(retort) => retort,
         }
     }
 
+    /// Roll against the favorites' extra weight. Returns a favorite if it wins the roll,
+    /// and None if we should fall back to picking uniformly.
+    async fn pick_favorite(&self, team: Option<&str>, cardinality: u64) -> Option<String> {
+        let favorites = self.store.weights(&self.favorites_for(team)).await.ok()?;
+        if favorites.is_empty() {
+            return None;
+        }
+        let cardinality = cardinality as f64;
+        let extra: f64 = favorites.iter().map(|(_, score)| score).sum();
+        let mut roll = self.dice.fraction() * (cardinality + extra);
+        if roll < cardinality {
+            return None;
+        }
+        roll -= cardinality;
+        let favorite = favorites.into_iter().find_map(|(shout, score)| {
+            if roll < score {
+                Some(shout)
+            } else {
+                roll -= score;
+                None
+            }
+        })?;
+        // A favorite that's been pruned from the pools since is no longer a favorite.
+        for pool in self.pools_for(team) {
+            if self.store.contains(&pool, &favorite).await.unwrap_or(true) {
+                return Some(favorite);
+            }
+        }
+        let _ = self.store.unweigh(&self.favorites_for(team), &favorite).await;
+        None
+    }
+
     /// Is the input LOUD or not?
     ///
     /// Believe it or not, this is the hardest job a LOUDBOT has. You don't want it
@@ -206,39 +1212,61 @@ impl Loudbot {
         result.to_uppercase() == result
     }
 
-    async fn trigger_report(mut r: MultiplexedConnection, t: &Trigger) -> String {
-        let key = format!("LB:{}_COUNT", t.set());
-        let count = match r.get::<&str, String>(&key).await {
-            Ok(c) => c,
-            Err(_) => "AN UNKNOWN NUMBER OF".to_string(),
+    async fn trigger_report(&self, set: &str) -> String {
+        let count = match self.store.count(&self.keys.trigger_count(set)).await {
+            Ok(Some(c)) => c.to_string(),
+            _ => "AN UNKNOWN NUMBER OF".to_string(),
         };
-        format!("{count} {} SHOUTS", t.set())
+        format!("{count} {set} SHOUTS")
     }
 
-    /// Respond to the `report` command. This is the only remaining place
-    /// that needs specific redis key strings.
-    async fn report(&self) -> Option<String> {
-        let mut r = self.redis().await.clone();
-
-        let count = match r.get::<&str, String>(COUNT).await {
-            Ok(c) => c,
-            Err(_) => "AN UNKNOWN NUMBER OF".to_string(),
+    /// Respond to the `report` command. The shouts we know are the ones this workspace
+    /// can hear; everything else is counted across every workspace we're in.
+    async fn report(&self, team: Option<&str>) -> Option<String> {
+        let count = match self.store.count(&self.keys.count()).await {
+            Ok(Some(c)) => c.to_string(),
+            _ => "AN UNKNOWN NUMBER OF".to_string(),
         };
-        let cardinality = match r.scard::<&str, u32>(YELLS).await {
-            Ok(c) => c.to_string(),
-            Err(_) => "AN UNKNOWN NUMBER OF".to_string(),
+        let mut cardinality = Some(0);
+        for pool in self.pools_for(team) {
+            cardinality = match self.store.cardinality(&pool).await {
+                Ok(c) => cardinality.map(|sum| sum + c),
+                Err(_) => None,
+            };
+        }
+        let cardinality = match cardinality {
+            Some(c) => c.to_string(),
+            None => "AN UNKNOWN NUMBER OF".to_string(),
         };
-        let mut lines = futures::future::join_all(self.triggers.iter().map(|t| {
-            let tmp = r.clone();
-            async move { Loudbot::trigger_report(tmp, t).await }
-        }))
-        .await;
+        let sets = self.sets();
+        let mut lines = futures::future::join_all(sets.iter().map(|set| self.trigger_report(set))).await;
 
-        let malcolms = match r.get::<&str, String>("LB:MALC_COUNT").await {
-            Ok(c) => c,
-            Err(_) => "ZERO".to_string(),
+        let malcolms = match self.store.count(&self.keys.trigger_count(MALCOLM_SET)).await {
+            Ok(Some(c)) => c.to_string(),
+            _ => "ZERO".to_string(),
         };
         lines.push(format!("MALCOLM TUCKER HAS BEEN SUMMONED {malcolms} TIMES."));
+        let duplicates = match self.store.count(&self.keys.duplicates()).await {
+            Ok(Some(c)) => c.to_string(),
+            _ => "ZERO".to_string(),
+        };
+        lines.push(format!("I HAVE IGNORED {duplicates} REPEATS."));
+        let retries = match self.store.count(&self.keys.retries()).await {
+            Ok(Some(c)) => c.to_string(),
+            _ => "ZERO".to_string(),
+        };
+        lines.push(format!("I HAVE IGNORED {retries} RETRIES I COULDN'T PLACE."));
+        let held_back = match self.store.count(&self.keys.held_back()).await {
+            Ok(Some(c)) => c.to_string(),
+            _ => "ZERO".to_string(),
+        };
+        let dropped = match self.store.count(&self.keys.dropped()).await {
+            Ok(Some(c)) => c.to_string(),
+            _ => "ZERO".to_string(),
+        };
+        lines.push(format!(
+            "I HAVE HELD BACK {held_back} YELLS AND IGNORED {dropped} SHOUTS FROM PEOPLE SHOUTING TOO MUCH."
+        ));
         let more = lines.join(" ");
 
         let version = env!("CARGO_PKG_VERSION");
@@ -249,6 +1277,7 @@ impl Loudbot {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::store::MemoryStore;
 
     #[test]
     fn is_loud_works() {
@@ -387,4 +1416,562 @@ mod tests {
             Retort::Trigger { retort: _, set: _ }
         ));
     }
+
+    #[test]
+    fn malcolm_can_be_a_maybe() {
+        use crate::dice::LoadedDice;
+        let loudie = Loudbot::with_store(Arc::new(MemoryStore::new()), 50);
+        let lucky = loudie.clone().with_dice(Arc::new(LoadedDice(50)));
+        assert!(matches!(
+            lucky.classify("fuck you"),
+            Retort::Trigger { retort: _, set } if set == "MALC"
+        ));
+        assert!(matches!(lucky.classify("fuckity bye"), Retort::Trigger { .. }));
+        let unlucky = loudie.with_dice(Arc::new(LoadedDice(51)));
+        assert!(matches!(unlucky.classify("fuck you"), Retort::None));
+        assert!(matches!(unlucky.classify("fuckity bye"), Retort::None));
+    }
+
+    fn remembering_loudbot() -> Loudbot {
+        Loudbot::with_store(Arc::new(MemoryStore::new()), 0)
+    }
+
+    fn here() -> Origin {
+        Origin {
+            team: Some("T1".to_string()),
+            channel: Some("C1".to_string()),
+            user: Some("U1".to_string()),
+            ts: Some("1.0".to_string()),
+            thread_ts: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn shouts_are_remembered_and_yelled() {
+        let loudie = remembering_loudbot();
+        assert!(loudie.process("I AM THE FIRST", &here()).await.is_none());
+        let reply = loudie
+            .process("I AM THE SECOND", &here())
+            .await
+            .expect("we should yell back");
+        assert_eq!(reply.yell.as_deref(), Some("I AM THE FIRST"));
+
+        let provenance = loudie
+            .provenance("I AM THE FIRST", None)
+            .await
+            .expect("we know who said it");
+        assert_eq!(provenance.user.as_deref(), Some("U1"));
+        assert_eq!(provenance.yelled, 1);
+
+        let report = loudie.report(None).await.expect("reports always say something");
+        assert!(report.contains("I HAVE 2 THINGS TO YELL AT YOU."));
+    }
+
+    #[tokio::test]
+    async fn forgotten_shouts_stay_forgotten() {
+        let loudie = remembering_loudbot();
+        loudie.process("FORGET ME", &here()).await;
+        let reply = loudie.process("KEEP ME", &here()).await.expect("we should yell back");
+        assert_eq!(reply.shout.as_deref(), Some("FORGET ME"));
+
+        let reply = loudie
+            .execute(Command::Forget, &here())
+            .await
+            .expect("forgetting is acknowledged");
+        assert_eq!(reply.emoji.as_deref(), Some("wastebasket"));
+        assert_eq!(loudie.random_yell().await.as_deref(), Some("KEEP ME"));
+        assert!(loudie.provenance("FORGET ME", None).await.is_none());
+    }
+
+    /// A LOUDBOT that has yelled THE OLD SHOUT, from U2, as the message at 2.0, and has
+    /// since yelled THE NEW SHOUT in the channel.
+    async fn yelled_twice() -> Loudbot {
+        let loudie = remembering_loudbot();
+        let them = Origin {
+            user: Some("U2".to_string()),
+            ..here()
+        };
+        loudie.process("THE OLD SHOUT", &them).await;
+        let reply = loudie
+            .process("THE NEW SHOUT", &here())
+            .await
+            .expect("we should yell back");
+        assert_eq!(reply.shout.as_deref(), Some("THE OLD SHOUT"));
+        loudie.posted("C1", "2.0", "THE OLD SHOUT").await;
+        loudie
+            .store
+            .set(&loudie.keys.last_yell("C1", None), "THE NEW SHOUT", None)
+            .await
+            .unwrap();
+        loudie
+    }
+
+    fn under_the_old_shout() -> Origin {
+        Origin {
+            ts: Some("3.0".to_string()),
+            thread_ts: Some("2.0".to_string()),
+            ..here()
+        }
+    }
+
+    #[tokio::test]
+    async fn replies_under_a_yell_forget_it() {
+        let loudie = yelled_twice().await;
+        let reply = loudie
+            .execute(Command::Forget, &under_the_old_shout())
+            .await
+            .expect("forgetting is acknowledged");
+        assert_eq!(reply.emoji.as_deref(), Some("wastebasket"));
+        assert!(loudie.provenance("THE OLD SHOUT", None).await.is_none());
+        assert!(loudie.provenance("THE NEW SHOUT", None).await.is_some());
+    }
+
+    #[tokio::test]
+    async fn replies_under_a_yell_ask_about_it() {
+        let loudie = yelled_twice().await;
+        let reply = loudie
+            .execute(Command::Who, &under_the_old_shout())
+            .await
+            .expect("we always have an answer");
+        let answer = reply.yell.expect("we should say who");
+        assert!(answer.starts_with("<@U2> SHOUTED THAT IN <#C1>"), "{answer}");
+        let reply = loudie
+            .execute(Command::Who, &here())
+            .await
+            .expect("we always have an answer");
+        assert!(reply.yell.expect("we should say who").starts_with("<@U1> SHOUTED THAT"));
+    }
+
+    #[tokio::test]
+    async fn workspaces_keep_their_records_apart() {
+        let loudie = remembering_loudbot().with_corpus(Corpus::PerTeam);
+        let elsewhere = Origin {
+            team: Some("T2".to_string()),
+            channel: Some("C9".to_string()),
+            user: Some("U9".to_string()),
+            ..here()
+        };
+        loudie.process("WE BOTH SAID THIS", &here()).await;
+        loudie.process("WE BOTH SAID THIS", &elsewhere).await;
+        let ours = loudie
+            .provenance("WE BOTH SAID THIS", Some("T1"))
+            .await
+            .expect("we know who said it");
+        assert_eq!(ours.user.as_deref(), Some("U1"));
+
+        let reply = loudie
+            .process("ANOTHER ONE", &elsewhere)
+            .await
+            .expect("we should yell back");
+        assert_eq!(reply.shout.as_deref(), Some("WE BOTH SAID THIS"));
+        let reply = loudie
+            .execute(Command::Who, &elsewhere)
+            .await
+            .expect("we always have an answer");
+        let answer = reply.yell.expect("we should say who");
+        assert!(answer.starts_with("<@U9> SHOUTED THAT IN <#C9>"), "{answer}");
+        assert!(answer.ends_with("I HAVE YELLED IT 1 TIMES."), "{answer}");
+
+        loudie.execute(Command::Forget, &elsewhere).await;
+        assert!(loudie.provenance("WE BOTH SAID THIS", Some("T2")).await.is_none());
+        let ours = loudie
+            .provenance("WE BOTH SAID THIS", Some("T1"))
+            .await
+            .expect("ours is still ours");
+        assert_eq!(ours.yelled, 0);
+    }
+
+    #[tokio::test]
+    async fn pruned_favorites_fall_out_of_favor() {
+        let loudie = remembering_loudbot();
+        let (yells, favorites) = (loudie.keys.yells(), loudie.keys.favorites());
+        loudie.store.remember(&yells, "I STAYED").await.unwrap();
+        loudie.store.weigh(&favorites, "I STAYED", 1.0).await.unwrap();
+        assert_eq!(loudie.pick_favorite(None, 0).await.as_deref(), Some("I STAYED"));
+
+        loudie.store.forget(&yells, "I STAYED").await.unwrap();
+        assert_eq!(loudie.pick_favorite(None, 0).await, None);
+        assert!(loudie.store.weights(&favorites).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn trigger_counts_are_reported() {
+        let loudie = remembering_loudbot().with_keys(Keys::new("LOUDER"));
+        assert!(loudie.process("cat fact", &here()).await.is_some());
+        assert!(loudie.process("cat fact please", &here()).await.is_some());
+        let report = loudie.report(None).await.expect("reports always say something");
+        assert!(report.contains("2 CATS SHOUTS"), "{report}");
+        assert!(report.contains("AN UNKNOWN NUMBER OF SHIPS SHOUTS"), "{report}");
+    }
+
+    #[tokio::test]
+    async fn workspaces_keep_their_shouts_apart() {
+        let loudie = remembering_loudbot().with_corpus(Corpus::PerTeam);
+        let elsewhere = Origin {
+            team: Some("T2".to_string()),
+            ..here()
+        };
+        loudie.process("ONLY IN THE FIRST TEAM", &here()).await;
+        assert!(loudie.process("ONLY IN THE SECOND TEAM", &elsewhere).await.is_none());
+        let reply = loudie
+            .process("ANOTHER ONE", &here())
+            .await
+            .expect("we should yell back");
+        assert_eq!(reply.shout.as_deref(), Some("ONLY IN THE FIRST TEAM"));
+
+        let shared = remembering_loudbot().with_corpus(Corpus::Shared);
+        shared
+            .store
+            .remember(&shared.keys.yells(), "EVERYONE KNOWS THIS")
+            .await
+            .unwrap();
+        let reply = shared
+            .process("HELLO FROM T1", &here())
+            .await
+            .expect("we should yell back");
+        assert_eq!(reply.shout.as_deref(), Some("EVERYONE KNOWS THIS"));
+        let report = shared.report(Some("T1")).await.expect("reports always say something");
+        assert!(report.contains("I HAVE 2 THINGS TO YELL AT YOU."), "{report}");
+    }
+
+    #[tokio::test]
+    async fn channels_have_their_own_settings() {
+        let loudie = remembering_loudbot().with_admins(vec!["U1".to_string()]);
+        let outsider = Origin {
+            user: Some("U2".to_string()),
+            ..here()
+        };
+        let reply = loudie
+            .execute(Command::Channel("remember off".to_string()), &outsider)
+            .await
+            .expect("commands are answered");
+        assert_eq!(reply.yell.as_deref(), Some("ONLY ADMINS CAN CHANGE THAT."));
+
+        loudie
+            .execute(Command::Channel("remember off".to_string()), &here())
+            .await;
+        loudie
+            .execute(Command::Channel("threads on".to_string()), &here())
+            .await;
+        let settings = loudie.channel_settings("C1").await;
+        assert!(!settings.remember && settings.threads_only);
+
+        loudie
+            .store
+            .remember(&loudie.keys.yells(), "I WAS HERE FIRST")
+            .await
+            .unwrap();
+        let reply = loudie
+            .process_in("DON'T REMEMBER ME", &here(), &settings)
+            .await
+            .expect("we should still yell back");
+        assert!(reply.thread);
+        assert_eq!(loudie.store.cardinality(&loudie.keys.yells()).await.unwrap(), 1);
+
+        let off = ChannelSettings {
+            enabled: false,
+            ..settings
+        };
+        assert!(loudie.process_in("IS ANYONE THERE", &here(), &off).await.is_none());
+        assert!(loudie.process_in("LOUDBOT REPORT", &here(), &off).await.is_some());
+        assert_eq!(loudie.channel_settings("C2").await, ChannelSettings::default());
+    }
+
+    #[tokio::test]
+    async fn snoozing_keeps_us_quiet() {
+        let loudie = remembering_loudbot();
+        loudie.process("I AM THE FIRST", &here()).await;
+        let reply = loudie
+            .process("LOUDBOT SHUT UP FOR 2 HOURS", &here())
+            .await
+            .expect("snoozing is acknowledged");
+        assert_eq!(reply.wake_in, Some(Duration::from_secs(2 * 60 * 60)));
+
+        assert!(loudie.process("I AM THE SECOND", &here()).await.is_none());
+        assert!(loudie.process("cat fact", &here()).await.is_none());
+        // Trigger phrases aren't learned as shouts, asleep or awake.
+        assert!(loudie.process("CAT FACT", &here()).await.is_none());
+        let elsewhere = Origin {
+            channel: Some("C2".to_string()),
+            ..here()
+        };
+        assert!(loudie.process("cat fact", &elsewhere).await.is_some());
+        assert_eq!(loudie.store.cardinality(&loudie.keys.yells()).await.unwrap(), 2);
+
+        let reply = loudie
+            .execute(Command::Wake, &here())
+            .await
+            .expect("waking is announced");
+        assert_eq!(reply.yell.as_deref(), Some(WAKE_UP));
+        assert_eq!(loudie.wake("C1").await, None);
+        assert!(loudie.process("I AM THE THIRD", &here()).await.is_some());
+
+        // An alarm set before a longer snooze doesn't wake us early.
+        loudie.execute(Command::Snooze("1 day".to_string()), &here()).await;
+        assert_eq!(loudie.alarm("C1").await, None);
+
+        // If we sleep through the alarm, the next message wakes us.
+        let alarm = loudie.keys.snooze("C1");
+        loudie
+            .store
+            .set(&alarm, &(now().as_secs() - 1).to_string(), None)
+            .await
+            .unwrap();
+        let reply = loudie
+            .process("I AM THE FOURTH", &here())
+            .await
+            .expect("we announce ourselves");
+        let yell = reply.yell.expect("we should say something");
+        assert!(yell.starts_with(&format!("{WAKE_UP}\n")), "{yell}");
+        assert!(loudie.provenance("I AM THE FOURTH", None).await.is_some());
+
+        let reply = loudie
+            .execute(Command::Snooze("for a while".to_string()), &here())
+            .await
+            .expect("we always answer");
+        assert_eq!(reply.wake_in, None);
+    }
+
+    #[tokio::test]
+    async fn spammers_are_limited() {
+        use crate::limits::{Rate, Scope, ANY_SET};
+        let loudie = remembering_loudbot().with_rate_limits(
+            RateLimits::default()
+                .limit(ANY_SET, Scope::Channel, Rate::parse("2/3600").unwrap())
+                .limit("YELLS", Scope::User, Rate::parse("3/3600").unwrap()),
+        );
+        loudie
+            .store
+            .remember(&loudie.keys.yells(), "I WAS HERE FIRST")
+            .await
+            .unwrap();
+        assert!(loudie.process("SHOUT ONE", &here()).await.is_some());
+        assert!(loudie.process("SHOUT TWO", &here()).await.is_some());
+        assert!(loudie.process("SHOUT THREE", &here()).await.is_none());
+        assert!(loudie.process("SHOUT FOUR", &here()).await.is_none());
+        // Three got remembered quietly; four was dropped.
+        assert_eq!(loudie.store.cardinality(&loudie.keys.yells()).await.unwrap(), 4);
+
+        let report = loudie.report(None).await.expect("reports always say something");
+        assert!(
+            report.contains("I HAVE HELD BACK 1 YELLS AND IGNORED 1 SHOUTS"),
+            "{report}"
+        );
+    }
+
+    #[tokio::test]
+    async fn spammers_are_ignored_while_we_snooze() {
+        use crate::limits::{Rate, Scope};
+        let loudie = remembering_loudbot().with_rate_limits(RateLimits::default().limit(
+            "YELLS",
+            Scope::User,
+            Rate::parse("1/3600").unwrap(),
+        ));
+        loudie.execute(Command::Snooze(String::new()), &here()).await;
+        assert!(loudie.process("SHOUT ONE", &here()).await.is_none());
+        assert!(loudie.process("SHOUT TWO", &here()).await.is_none());
+        assert_eq!(loudie.store.cardinality(&loudie.keys.yells()).await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn yelling_back_is_left_to_chance() {
+        use crate::dice::LoadedDice;
+        let loudie = remembering_loudbot()
+            .with_yell_chance(30)
+            .with_dice(Arc::new(LoadedDice(50)));
+        loudie
+            .store
+            .remember(&loudie.keys.yells(), "I WAS HERE FIRST")
+            .await
+            .unwrap();
+        assert!(loudie.process("NOBODY ANSWERS ME", &here()).await.is_none());
+        assert_eq!(loudie.store.cardinality(&loudie.keys.yells()).await.unwrap(), 2);
+
+        let busy = ChannelSettings {
+            yell_chance: Some(60),
+            ..ChannelSettings::default()
+        };
+        assert!(loudie
+            .process_in("THIS CHANNEL ANSWERS", &here(), &busy)
+            .await
+            .is_some());
+        let silent = ChannelSettings {
+            yell_chance: Some(0),
+            ..ChannelSettings::default()
+        };
+        let loudie = loudie.with_dice(Arc::new(LoadedDice(1)));
+        assert!(loudie.process_in("NOT EVEN A LITTLE", &here(), &silent).await.is_none());
+        assert!(loudie.process("ALMOST ALWAYS", &here()).await.is_some());
+    }
+
+    #[tokio::test]
+    async fn triggers_reload_without_a_restart() {
+        let dir = std::env::temp_dir().join(format!("loudcrab-triggers-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("TRIGGERS.toml");
+        let crabs = "[[trigger]]\nname = 'CRABS'\npattern = '(?i)CRAB'\nresponses = ['PINCH']\n";
+        std::fs::write(&path, crabs).unwrap();
+
+        let loudie = remembering_loudbot().with_trigger_file(&path).unwrap();
+        let running = loudie.clone();
+        assert!(matches!(running.classify("cat fact"), Retort::None));
+        assert!(matches!(running.classify("crab rave"), Retort::Trigger { .. }));
+
+        let lobsters = crabs.replace("CRAB", "LOBSTER");
+        std::fs::write(&path, &lobsters).unwrap();
+        let reply = loudie
+            .execute(Command::Reload, &here())
+            .await
+            .expect("reloading is acknowledged");
+        assert_eq!(reply.yell.as_deref(), Some("I NOW KNOW 1 TRIGGERS."));
+        assert!(matches!(running.classify("lobster rave"), Retort::Trigger { .. }));
+
+        std::fs::write(&path, lobsters.replace("LOBSTER", "(LOBSTER")).unwrap();
+        assert!(loudie.reload_triggers().is_err());
+        assert!(matches!(running.classify("lobster rave"), Retort::Trigger { .. }));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn anybody_can_respond() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        /// Answers every other knock, and never twice in a row.
+        struct Door(AtomicUsize);
+
+        impl Responder for Door {
+            fn set(&self) -> &str {
+                "DOOR"
+            }
+
+            fn respond(&self, text: &str, _origin: &Origin, _chance: Option<u8>, _draw: &mut Draw) -> Option<Retort> {
+                if !text.contains("KNOCK") || self.0.fetch_add(1, Ordering::SeqCst) % 2 == 1 {
+                    return None;
+                }
+                Some(Retort::Canned("WHO'S THERE".to_string()))
+            }
+        }
+
+        let loudie = remembering_loudbot().with_responder(Arc::new(Door(AtomicUsize::new(0))));
+        assert!(matches!(loudie.classify("KNOCK KNOCK"), Retort::Canned(_)));
+        assert!(matches!(loudie.classify("KNOCK KNOCK"), Retort::Remember(_)));
+        assert!(matches!(loudie.classify("KNOCK KNOCK"), Retort::Canned(_)));
+        // Triggers still get asked first, and a reload keeps the door and its count.
+        assert!(matches!(
+            loudie.classify("KNOCK KNOCK CAT FACT"),
+            Retort::Trigger { .. }
+        ));
+        loudie.reload_triggers().unwrap();
+        assert!(matches!(loudie.classify("KNOCK KNOCK"), Retort::Remember(_)));
+        assert!(matches!(loudie.classify("KNOCK KNOCK"), Retort::Canned(_)));
+        assert_eq!(
+            loudie.sets(),
+            vec!["CATS", "STARS", "SHIPS", "STRATEGIES", "MALC", "DOOR"]
+        );
+    }
+
+    #[tokio::test]
+    async fn triggers_take_turns_and_cool_down() {
+        let store: Arc<dyn ShoutStore> = Arc::new(MemoryStore::new());
+        let restart = || {
+            let responses = |r: &[&str]| r.iter().map(|r| r.to_string()).collect();
+            Loudbot::with_store(store.clone(), 0).with_triggers(vec![
+                Trigger::new(
+                    "CRABS",
+                    Regex::new("(?i)CRAB").unwrap(),
+                    responses(&["PINCH", "SCUTTLE", "MOLT"]),
+                    100,
+                ),
+                Trigger::new(
+                    "LOBSTERS",
+                    Regex::new("(?i)LOBSTER").unwrap(),
+                    responses(&["BOIL"]),
+                    100,
+                )
+                .with_cooldown(Duration::from_secs(600)),
+            ])
+        };
+        let yell = |reply: Option<Reply>| reply.and_then(|r| r.yell).expect("we should have yelled");
+
+        let loudie = restart();
+        let mut heard = vec![yell(loudie.process("crab rave", &here()).await)];
+        heard.push(yell(loudie.process("crab rave", &here()).await));
+        // Restarting doesn't shuffle the bag.
+        let loudie = restart();
+        heard.push(yell(loudie.process("crab rave", &here()).await));
+        heard.sort();
+        assert_eq!(heard, vec!["MOLT", "PINCH", "SCUTTLE"]);
+
+        assert_eq!(yell(loudie.process("lobster rave", &here()).await), "BOIL");
+        assert!(loudie.process("lobster rave", &here()).await.is_none());
+        let elsewhere = Origin {
+            channel: Some("C2".to_string()),
+            ..here()
+        };
+        assert!(loudie.process("lobster rave", &elsewhere).await.is_some());
+    }
+
+    fn lobster_loudbot(store: &Arc<dyn ShoutStore>) -> Loudbot {
+        Loudbot::with_store(store.clone(), 0).with_triggers(vec![Trigger::new(
+            "LOBSTERS",
+            Regex::new("(?i)LOBSTER").unwrap(),
+            vec!["BOIL".to_string()],
+            100,
+        )
+        .with_cooldown(Duration::from_secs(600))])
+    }
+
+    #[tokio::test]
+    async fn held_back_triggers_stay_ready() {
+        use crate::limits::{Rate, Scope};
+        let store: Arc<dyn ShoutStore> = Arc::new(MemoryStore::new());
+        let loudie = lobster_loudbot(&store).with_rate_limits(RateLimits::default().limit(
+            "LOBSTERS",
+            Scope::Channel,
+            Rate::parse("1/3600").unwrap(),
+        ));
+        assert_eq!(loudie.limit("LOBSTERS", &here()).await, Verdict::Yell);
+        assert!(loudie.process("lobster rave", &here()).await.is_none());
+        // Nothing went out, so nothing is cooling down.
+        let loudie = lobster_loudbot(&store);
+        let reply = loudie.process("lobster rave", &here()).await;
+        assert_eq!(reply.and_then(|r| r.yell).as_deref(), Some("BOIL"));
+    }
+
+    #[tokio::test]
+    async fn channels_take_turns() {
+        let store: Arc<dyn ShoutStore> = Arc::new(MemoryStore::new());
+        let loudie = lobster_loudbot(&store);
+        let turn = loudie.channel_turn("C1").await;
+        let waiting = {
+            let loudie = loudie.clone();
+            tokio::spawn(async move { loudie.process("lobster rave", &here()).await })
+        };
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!waiting.is_finished(), "we should wait our turn");
+        let elsewhere = Origin {
+            channel: Some("C2".to_string()),
+            ..here()
+        };
+        assert!(loudie.process("lobster rave", &elsewhere).await.is_some());
+        drop(turn);
+        assert!(waiting.await.unwrap().is_some());
+        assert!(loudie.process("lobster rave", &here()).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn duplicate_events_are_noticed() {
+        let loudie = remembering_loudbot();
+        assert!(loudie.first_sighting("Ev1").await);
+        assert!(!loudie.first_sighting("Ev1").await);
+        assert!(loudie.first_sighting("Ev2").await);
+        // Retries are only dropped when we have nothing better to go on.
+        assert!(loudie.first_delivery(Some("Ev3"), Some(1)).await);
+        assert!(!loudie.first_delivery(Some("Ev3"), None).await);
+        assert!(loudie.first_delivery(None, None).await);
+        assert!(!loudie.first_delivery(None, Some(2)).await);
+        let report = loudie.report(None).await.expect("reports always say something");
+        assert!(report.contains("I HAVE IGNORED 2 REPEATS."));
+        assert!(report.contains("I HAVE IGNORED 1 RETRIES I COULDN'T PLACE."));
+    }
 }
@@ -0,0 +1,32 @@
+//! WHO'S SHOUTING, AND WHERE. Front ends describe where each message came from, and
+//! everything from triggers to provenance records works from that description.
+use crate::keys::Keys;
+
+/// Where a message came from. Front ends fill in whatever they know.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Origin {
+    /// The workspace, or whatever the chat service calls it.
+    pub team: Option<String>,
+    pub channel: Option<String>,
+    /// Who sent the message.
+    pub user: Option<String>,
+    /// The message's own timestamp.
+    pub ts: Option<String>,
+    /// The timestamp of the thread the message is in, if it's in one.
+    pub thread_ts: Option<String>,
+}
+
+impl Origin {
+    /// The keys under which we remember the last shout we yelled in reply to a message
+    /// from here: one for the channel, and one for the thread if there is one.
+    pub(crate) fn last_yell_keys(&self, keys: &Keys) -> Vec<String> {
+        let mut found = Vec::new();
+        if let Some(channel) = &self.channel {
+            found.push(keys.last_yell(channel, None));
+            if let Some(thread) = &self.thread_ts {
+                found.push(keys.last_yell(channel, Some(thread)));
+            }
+        }
+        found
+    }
+}
@@ -0,0 +1,86 @@
+//! WHO SHOUTED THAT? Every shout LOUDBOT learns gets a record of where it came from,
+//! filed under a stable id derived from the shout itself.
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::now;
+use crate::origin::Origin;
+
+/// Where a shout came from, and how popular it's been since.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Provenance {
+    /// Who shouted it first.
+    pub user: Option<String>,
+    /// Where they shouted it.
+    pub channel: Option<String>,
+    pub team: Option<String>,
+    /// The timestamp of the message they shouted it in.
+    pub ts: Option<String>,
+    /// When LOUDBOT first heard it, in epoch seconds.
+    pub first_seen: u64,
+    /// How many times LOUDBOT has yelled it since. This is counted separately and
+    /// filled in when the record is read.
+    #[serde(default)]
+    pub yelled: u64,
+}
+
+impl Provenance {
+    /// A fresh record for a shout we've just heard.
+    pub fn new(origin: &Origin) -> Self {
+        Provenance {
+            user: origin.user.clone(),
+            channel: origin.channel.clone(),
+            team: origin.team.clone(),
+            ts: origin.ts.clone(),
+            first_seen: now().as_secs(),
+            yelled: 0,
+        }
+    }
+
+    /// The day we first heard the shout, as YYYY-MM-DD in UTC.
+    pub fn first_seen_date(&self) -> String {
+        // Howard Hinnant's days-to-civil algorithm, so we don't need a date crate for this.
+        let days = (self.first_seen / 86_400) as i64 + 719_468;
+        let era = days.div_euclid(146_097);
+        let doe = days.rem_euclid(146_097);
+        let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era * 400 + i64::from(month <= 2);
+        format!("{year:04}-{month:02}-{day:02}")
+    }
+}
+
+/// The stable id for a shout: the first 16 hex digits of the sha256 of its text, exactly
+/// as stored.
+pub fn shout_id(shout: &str) -> String {
+    let digest = Sha256::digest(shout.as_bytes());
+    hex::encode(&digest[..8])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shout_ids_are_stable() {
+        assert_eq!(shout_id("HELLO"), "3733cd977ff8eb18");
+        assert_eq!(shout_id("HELLO"), shout_id("HELLO"));
+        assert_ne!(shout_id("HELLO"), shout_id("HELLO!"));
+        assert_eq!(shout_id("").len(), 16);
+    }
+
+    #[test]
+    fn dates_are_dates() {
+        let mut provenance = Provenance::default();
+        assert_eq!(provenance.first_seen_date(), "1970-01-01");
+        provenance.first_seen = 1_392_076_800;
+        assert_eq!(provenance.first_seen_date(), "2014-02-11");
+        provenance.first_seen = 951_782_400;
+        assert_eq!(provenance.first_seen_date(), "2000-02-29");
+        provenance.first_seen = 1_666_051_199;
+        assert_eq!(provenance.first_seen_date(), "2022-10-17");
+    }
+}
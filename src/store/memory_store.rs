@@ -0,0 +1,320 @@
+//! A shout store that lives and dies with the process. Good for tests, and for tiny
+//! LOUDBOTs that are happy to start over every time they restart.
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use rand::seq::IteratorRandom;
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use super::{ShoutStore, SWEEP_EVERY};
+
+/// Everything we're holding, by kind of key, plus when keys expire.
+#[derive(Debug, Default)]
+struct Memory {
+    sets: HashMap<String, HashSet<String>>,
+    values: HashMap<String, String>,
+    weights: HashMap<String, HashMap<String, f64>>,
+    logs: HashMap<String, VecDeque<String>>,
+    expiries: HashMap<String, Instant>,
+    /// Operations since we last swept.
+    operations: u64,
+}
+
+impl Memory {
+    /// Drop the key if its time is up. Called before touching any key.
+    fn expire(&mut self, key: &str) {
+        match self.expiries.get(key) {
+            Some(when) if *when <= Instant::now() => self.delete(key),
+            _ => {}
+        }
+    }
+
+    /// Drop every key whose time is up.
+    fn sweep(&mut self) {
+        let now = Instant::now();
+        let expired: Vec<String> = self
+            .expiries
+            .iter()
+            .filter(|(_, when)| **when <= now)
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in expired {
+            self.delete(&key);
+        }
+        self.operations = 0;
+    }
+
+    fn delete(&mut self, key: &str) {
+        self.sets.remove(key);
+        self.values.remove(key);
+        self.weights.remove(key);
+        self.logs.remove(key);
+        self.expiries.remove(key);
+    }
+
+    fn exists(&self, key: &str) -> bool {
+        self.sets.contains_key(key)
+            || self.values.contains_key(key)
+            || self.weights.contains_key(key)
+            || self.logs.contains_key(key)
+    }
+
+    fn set(&mut self, key: &str, value: &str, ttl: Option<Duration>) {
+        self.values.insert(key.to_string(), value.to_string());
+        match ttl {
+            Some(ttl) => self.expiries.insert(key.to_string(), Instant::now() + ttl),
+            None => self.expiries.remove(key),
+        };
+    }
+}
+
+/// A shout store that keeps everything in a hashmap.
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    memory: Mutex<Memory>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        MemoryStore::default()
+    }
+
+    /// Lock our memory, tidying away the given key first if it's expired, and every
+    /// expired key if it's been a while.
+    fn recall(&self, key: &str) -> Result<std::sync::MutexGuard<'_, Memory>> {
+        let mut memory = self.memory.lock().map_err(|_| anyhow!("memory store lock poisoned"))?;
+        memory.operations += 1;
+        if memory.operations >= SWEEP_EVERY {
+            memory.sweep();
+        } else {
+            memory.expire(key);
+        }
+        Ok(memory)
+    }
+}
+
+#[async_trait]
+impl ShoutStore for MemoryStore {
+    async fn remember(&self, set: &str, member: &str) -> Result<bool> {
+        let mut memory = self.recall(set)?;
+        Ok(memory
+            .sets
+            .entry(set.to_string())
+            .or_default()
+            .insert(member.to_string()))
+    }
+
+    async fn select(&self, set: &str) -> Result<Option<String>> {
+        let memory = self.recall(set)?;
+        Ok(memory
+            .sets
+            .get(set)
+            .and_then(|members| members.iter().choose(&mut rand::thread_rng()).cloned()))
+    }
+
+    async fn forget(&self, set: &str, member: &str) -> Result<bool> {
+        let mut memory = self.recall(set)?;
+        let removed = match memory.sets.get_mut(set) {
+            Some(members) => members.remove(member),
+            None => false,
+        };
+        if matches!(memory.sets.get(set), Some(members) if members.is_empty()) {
+            memory.delete(set);
+        }
+        Ok(removed)
+    }
+
+    async fn cardinality(&self, set: &str) -> Result<u64> {
+        let memory = self.recall(set)?;
+        Ok(memory.sets.get(set).map_or(0, |members| members.len() as u64))
+    }
+
+    async fn contains(&self, set: &str, member: &str) -> Result<bool> {
+        let memory = self.recall(set)?;
+        Ok(matches!(memory.sets.get(set), Some(members) if members.contains(member)))
+    }
+
+    async fn increment(&self, counter: &str) -> Result<u64> {
+        let mut memory = self.recall(counter)?;
+        let count = match memory.values.get(counter) {
+            Some(v) => v
+                .parse::<u64>()
+                .map_err(|_| anyhow!("{counter} does not hold a number"))?,
+            None => 0,
+        } + 1;
+        memory.values.insert(counter.to_string(), count.to_string());
+        Ok(count)
+    }
+
+    async fn count(&self, counter: &str) -> Result<Option<u64>> {
+        let memory = self.recall(counter)?;
+        match memory.values.get(counter) {
+            Some(v) => Ok(Some(
+                v.parse().map_err(|_| anyhow!("{counter} does not hold a number"))?,
+            )),
+            None => Ok(None),
+        }
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<String>> {
+        let memory = self.recall(key)?;
+        Ok(memory.values.get(key).cloned())
+    }
+
+    async fn take(&self, key: &str) -> Result<Option<String>> {
+        let mut memory = self.recall(key)?;
+        let value = memory.values.remove(key);
+        if value.is_some() {
+            memory.expiries.remove(key);
+        }
+        Ok(value)
+    }
+
+    async fn set(&self, key: &str, value: &str, ttl: Option<Duration>) -> Result<()> {
+        let mut memory = self.recall(key)?;
+        memory.set(key, value, ttl);
+        Ok(())
+    }
+
+    async fn set_if_absent(&self, key: &str, value: &str, ttl: Option<Duration>) -> Result<bool> {
+        let mut memory = self.recall(key)?;
+        if memory.values.contains_key(key) {
+            return Ok(false);
+        }
+        memory.set(key, value, ttl);
+        Ok(true)
+    }
+
+    async fn expire(&self, key: &str, ttl: Duration) -> Result<()> {
+        let mut memory = self.recall(key)?;
+        if memory.exists(key) {
+            memory.expiries.insert(key.to_string(), Instant::now() + ttl);
+        }
+        Ok(())
+    }
+
+    async fn delete(&self, keys: &[String]) -> Result<()> {
+        let mut memory = self.memory.lock().map_err(|_| anyhow!("memory store lock poisoned"))?;
+        for key in keys {
+            memory.delete(key);
+        }
+        Ok(())
+    }
+
+    async fn weigh(&self, set: &str, member: &str, by: f64) -> Result<f64> {
+        let mut memory = self.recall(set)?;
+        let weight = memory
+            .weights
+            .entry(set.to_string())
+            .or_default()
+            .entry(member.to_string())
+            .or_default();
+        *weight += by;
+        Ok(*weight)
+    }
+
+    async fn unweigh(&self, set: &str, member: &str) -> Result<()> {
+        let mut memory = self.recall(set)?;
+        if let Some(members) = memory.weights.get_mut(set) {
+            members.remove(member);
+        }
+        Ok(())
+    }
+
+    async fn weights(&self, set: &str) -> Result<Vec<(String, f64)>> {
+        let memory = self.recall(set)?;
+        let mut weights: Vec<(String, f64)> = memory
+            .weights
+            .get(set)
+            .map(|members| members.iter().map(|(m, w)| (m.clone(), *w)).collect())
+            .unwrap_or_default();
+        // Lowest weight first, like redis does it.
+        weights.sort_by(|a, b| a.1.total_cmp(&b.1).then_with(|| a.0.cmp(&b.0)));
+        Ok(weights)
+    }
+
+    async fn log(&self, key: &str, entry: &str, limit: usize) -> Result<()> {
+        let mut memory = self.recall(key)?;
+        let log = memory.logs.entry(key.to_string()).or_default();
+        log.push_front(entry.to_string());
+        if limit > 0 {
+            log.truncate(limit);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::outlive_untouched_keys;
+
+    #[tokio::test]
+    async fn sets_behave_like_sets() {
+        let store = MemoryStore::new();
+        assert!(store.remember("SET", "HELLO").await.unwrap());
+        assert!(!store.remember("SET", "HELLO").await.unwrap());
+        assert!(store.remember("SET", "GOODBYE").await.unwrap());
+        assert_eq!(store.cardinality("SET").await.unwrap(), 2);
+
+        let picked = store
+            .select("SET")
+            .await
+            .unwrap()
+            .expect("there should be something to pick");
+        assert!(picked == "HELLO" || picked == "GOODBYE");
+
+        assert!(store.forget("SET", "HELLO").await.unwrap());
+        assert!(!store.forget("SET", "HELLO").await.unwrap());
+        assert_eq!(store.select("SET").await.unwrap().as_deref(), Some("GOODBYE"));
+        assert_eq!(store.select("NOTHING").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn counters_count() {
+        let store = MemoryStore::new();
+        assert_eq!(store.count("COUNT").await.unwrap(), None);
+        assert_eq!(store.increment("COUNT").await.unwrap(), 1);
+        assert_eq!(store.increment("COUNT").await.unwrap(), 2);
+        assert_eq!(store.count("COUNT").await.unwrap(), Some(2));
+    }
+
+    #[tokio::test]
+    async fn values_expire() {
+        let store = MemoryStore::new();
+        assert!(store.set_if_absent("KEY", "1", None).await.unwrap());
+        assert!(!store.set_if_absent("KEY", "2", None).await.unwrap());
+        assert_eq!(store.get("KEY").await.unwrap().as_deref(), Some("1"));
+
+        store.set("BRIEF", "1", Some(Duration::from_millis(1))).await.unwrap();
+        std::thread::sleep(Duration::from_millis(5));
+        assert_eq!(store.get("BRIEF").await.unwrap(), None);
+        assert!(store.set_if_absent("BRIEF", "2", None).await.unwrap());
+
+        assert_eq!(store.take("KEY").await.unwrap().as_deref(), Some("1"));
+        assert_eq!(store.take("KEY").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn untouched_keys_expire_too() {
+        let store = MemoryStore::new();
+        outlive_untouched_keys(&store).await;
+        let memory = store.memory.lock().unwrap();
+        assert_eq!(memory.values.len(), 1);
+        assert!(memory.expiries.is_empty());
+    }
+
+    #[tokio::test]
+    async fn logs_are_capped_unless_told_otherwise() {
+        let store = MemoryStore::new();
+        for entry in ["ONE", "TWO", "THREE"] {
+            store.log("CAPPED", entry, 2).await.unwrap();
+            store.log("UNCAPPED", entry, 0).await.unwrap();
+        }
+        let memory = store.memory.lock().unwrap();
+        assert_eq!(memory.logs["CAPPED"], ["THREE", "TWO"]);
+        assert_eq!(memory.logs["UNCAPPED"].len(), 3);
+    }
+}
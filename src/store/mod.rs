@@ -0,0 +1,97 @@
+//! LOUDBOT'S MEMORY. The brain only needs a handful of operations from whatever is
+//! holding its shouts, so they live behind a trait. Redis is the traditional home;
+//! the in-memory store is for tests and for LOUDBOTs that don't mind amnesia, and
+//! SQLite (with the `sqlite` feature) is for small hosts that want to remember.
+use anyhow::Result;
+use async_trait::async_trait;
+
+use std::time::Duration;
+
+mod memory_store;
+pub use memory_store::MemoryStore;
+mod redis_store;
+pub use redis_store::RedisStore;
+#[cfg(feature = "sqlite")]
+mod sqlite_store;
+#[cfg(feature = "sqlite")]
+pub use sqlite_store::SqliteStore;
+
+/// How many operations go by between sweeps for expired keys, in stores that have to
+/// expire keys themselves. Most keys with a time to live are written once and never
+/// touched again, so they'd never expire otherwise.
+const SWEEP_EVERY: u64 = 1000;
+
+/// Everything LOUDBOT needs from a backing store. The operations are modeled on the
+/// redis commands the brain has always used, so keys can hold sets, counters, plain
+/// values, weighted sets, or capped logs. Any key can be given a time to live.
+#[async_trait]
+pub trait ShoutStore: Send + Sync {
+    /// Add a member to a set. Returns true if it wasn't there already. (`SADD`)
+    async fn remember(&self, set: &str, member: &str) -> Result<bool>;
+
+    /// Pick a random member of a set, if it has any. (`SRANDMEMBER`)
+    async fn select(&self, set: &str) -> Result<Option<String>>;
+
+    /// Remove a member from a set. Returns true if it was there. (`SREM`)
+    async fn forget(&self, set: &str, member: &str) -> Result<bool>;
+
+    /// How many members a set has. (`SCARD`)
+    async fn cardinality(&self, set: &str) -> Result<u64>;
+
+    /// Whether a set has this member. (`SISMEMBER`)
+    async fn contains(&self, set: &str, member: &str) -> Result<bool>;
+
+    /// Add one to a counter and return the new count. (`INCR`)
+    async fn increment(&self, counter: &str) -> Result<u64>;
+
+    /// Read a counter. None means it has never been incremented. (`GET`)
+    async fn count(&self, counter: &str) -> Result<Option<u64>>;
+
+    /// Read a plain value. (`GET`)
+    async fn get(&self, key: &str) -> Result<Option<String>>;
+
+    /// Read a plain value and delete it in one step, so only one caller ever gets it.
+    /// (`GETDEL`)
+    async fn take(&self, key: &str) -> Result<Option<String>>;
+
+    /// Write a plain value, optionally expiring it. (`SET`, `SETEX`)
+    async fn set(&self, key: &str, value: &str, ttl: Option<Duration>) -> Result<()>;
+
+    /// Write a plain value only if there isn't one already. Returns true if we wrote it.
+    /// (`SET NX`)
+    async fn set_if_absent(&self, key: &str, value: &str, ttl: Option<Duration>) -> Result<bool>;
+
+    /// Expire a key of any kind after the given time. (`EXPIRE`)
+    async fn expire(&self, key: &str, ttl: Duration) -> Result<()>;
+
+    /// Delete keys of any kind. (`DEL`)
+    async fn delete(&self, keys: &[String]) -> Result<()>;
+
+    /// Add to a member's weight in a weighted set, returning the new weight. (`ZINCRBY`)
+    async fn weigh(&self, set: &str, member: &str, by: f64) -> Result<f64>;
+
+    /// Remove a member from a weighted set. (`ZREM`)
+    async fn unweigh(&self, set: &str, member: &str) -> Result<()>;
+
+    /// Every member of a weighted set along with its weight. (`ZRANGE WITHSCORES`)
+    async fn weights(&self, set: &str) -> Result<Vec<(String, f64)>>;
+
+    /// Push an entry onto the front of a log, keeping only the newest `limit` entries, or
+    /// every entry if `limit` is zero. (`LPUSH` and `LTRIM`)
+    async fn log(&self, key: &str, entry: &str, limit: usize) -> Result<()>;
+}
+
+/// Fill a store with keys that expire without anybody touching them again, then keep it
+/// busy with other keys until it should have swept them away. Only `FOREVER` should be left.
+#[cfg(test)]
+async fn outlive_untouched_keys(store: &dyn ShoutStore) {
+    for event in 0..100 {
+        let key = format!("EVENT:{event}");
+        store.set(&key, "1", Some(Duration::from_millis(1))).await.unwrap();
+    }
+    store.set("FOREVER", "1", None).await.unwrap();
+    std::thread::sleep(Duration::from_millis(5));
+    for _ in 0..SWEEP_EVERY {
+        store.get("FOREVER").await.unwrap();
+    }
+}
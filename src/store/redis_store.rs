@@ -0,0 +1,181 @@
+//! The traditional LOUDBOT memory: a redis server.
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use redis::aio::MultiplexedConnection;
+use redis::{AsyncCommands, RedisResult};
+use tokio::sync::Mutex;
+
+use std::time::Duration;
+
+use super::ShoutStore;
+
+/// Redis wants expiries in whole seconds, and zero means something else entirely.
+fn seconds(ttl: Duration) -> usize {
+    std::cmp::max(ttl.as_secs(), 1) as usize
+}
+
+/// A shout store backed by redis. Each store has its own connection, made the first
+/// time it's needed and remade after redis goes away.
+pub struct RedisStore {
+    /// our redis client
+    client: redis::Client,
+    /// Our connection, if we have a live one.
+    connection: Mutex<Option<MultiplexedConnection>>,
+}
+
+impl RedisStore {
+    pub fn new(redis_uri: &str) -> Result<Self> {
+        let client =
+            redis::Client::open(redis_uri).with_context(|| format!("Unable to create redis client @ {}", redis_uri))?;
+        Ok(RedisStore {
+            client,
+            connection: Mutex::new(None),
+        })
+    }
+
+    /// Fetch our redis connection, connecting if we don't have one.
+    async fn redis(&self) -> Result<MultiplexedConnection> {
+        let mut connection = self.connection.lock().await;
+        if let Some(r) = connection.as_ref() {
+            return Ok(r.clone());
+        }
+        let r = self
+            .client
+            .get_multiplexed_async_std_connection()
+            .await
+            .context("Unable to connect to redis")?;
+        *connection = Some(r.clone());
+        Ok(r)
+    }
+
+    /// Pass along the result of a redis command. If the command failed because we lost
+    /// our connection, forget it so the next command reconnects.
+    async fn check<T>(&self, result: RedisResult<T>) -> Result<T> {
+        if let Err(e) = &result {
+            if e.is_connection_dropped() || e.is_io_error() || e.is_connection_refusal() || e.is_timeout() {
+                log::warn!("Lost our redis connection; will reconnect. {:?}", e);
+                *self.connection.lock().await = None;
+            }
+        }
+        Ok(result?)
+    }
+}
+
+#[async_trait]
+impl ShoutStore for RedisStore {
+    async fn remember(&self, set: &str, member: &str) -> Result<bool> {
+        let mut r = self.redis().await?;
+        let added: u32 = self.check(r.sadd(set, member).await).await?;
+        Ok(added > 0)
+    }
+
+    async fn select(&self, set: &str) -> Result<Option<String>> {
+        let mut r = self.redis().await?;
+        self.check(r.srandmember(set).await).await
+    }
+
+    async fn forget(&self, set: &str, member: &str) -> Result<bool> {
+        let mut r = self.redis().await?;
+        let removed: u32 = self.check(r.srem(set, member).await).await?;
+        Ok(removed > 0)
+    }
+
+    async fn cardinality(&self, set: &str) -> Result<u64> {
+        let mut r = self.redis().await?;
+        self.check(r.scard(set).await).await
+    }
+
+    async fn contains(&self, set: &str, member: &str) -> Result<bool> {
+        let mut r = self.redis().await?;
+        self.check(r.sismember(set, member).await).await
+    }
+
+    async fn increment(&self, counter: &str) -> Result<u64> {
+        let mut r = self.redis().await?;
+        self.check(r.incr(counter, 1_u64).await).await
+    }
+
+    async fn count(&self, counter: &str) -> Result<Option<u64>> {
+        let mut r = self.redis().await?;
+        self.check(r.get(counter).await).await
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<String>> {
+        let mut r = self.redis().await?;
+        self.check(r.get(key).await).await
+    }
+
+    async fn take(&self, key: &str) -> Result<Option<String>> {
+        let mut r = self.redis().await?;
+        self.check(redis::cmd("GETDEL").arg(key).query_async(&mut r).await)
+            .await
+    }
+
+    async fn set(&self, key: &str, value: &str, ttl: Option<Duration>) -> Result<()> {
+        let mut r = self.redis().await?;
+        let result = match ttl {
+            Some(ttl) => r.set_ex(key, value, seconds(ttl)).await,
+            None => r.set(key, value).await,
+        };
+        self.check(result).await
+    }
+
+    async fn set_if_absent(&self, key: &str, value: &str, ttl: Option<Duration>) -> Result<bool> {
+        let mut r = self.redis().await?;
+        let mut cmd = redis::cmd("SET");
+        cmd.arg(key).arg(value).arg("NX");
+        if let Some(ttl) = ttl {
+            cmd.arg("EX").arg(seconds(ttl));
+        }
+        let result: Option<String> = self.check(cmd.query_async(&mut r).await).await?;
+        Ok(result.is_some())
+    }
+
+    async fn expire(&self, key: &str, ttl: Duration) -> Result<()> {
+        let mut r = self.redis().await?;
+        self.check(r.expire(key, seconds(ttl)).await).await
+    }
+
+    async fn delete(&self, keys: &[String]) -> Result<()> {
+        if keys.is_empty() {
+            return Ok(());
+        }
+        let mut r = self.redis().await?;
+        self.check(r.del(keys).await).await
+    }
+
+    async fn weigh(&self, set: &str, member: &str, by: f64) -> Result<f64> {
+        let mut r = self.redis().await?;
+        self.check(r.zincr(set, member, by).await).await
+    }
+
+    async fn unweigh(&self, set: &str, member: &str) -> Result<()> {
+        let mut r = self.redis().await?;
+        self.check(r.zrem(set, member).await).await
+    }
+
+    async fn weights(&self, set: &str) -> Result<Vec<(String, f64)>> {
+        let mut r = self.redis().await?;
+        self.check(r.zrange_withscores(set, 0, -1).await).await
+    }
+
+    async fn log(&self, key: &str, entry: &str, limit: usize) -> Result<()> {
+        let mut r = self.redis().await?;
+        self.check::<()>(r.lpush(key, entry).await).await?;
+        self.check(r.ltrim(key, 0, limit as isize - 1).await).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn unreachable_redis_is_an_error_not_a_panic() {
+        // Nothing listens on port 1, so this fails to connect every time we try.
+        let store = RedisStore::new("redis://127.0.0.1:1").expect("the uri is fine");
+        assert!(store.remember("LB:YELLS", "HELLO").await.is_err());
+        assert!(store.select("LB:YELLS").await.is_err());
+        assert!(store.connection.lock().await.is_none());
+    }
+}
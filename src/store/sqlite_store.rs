@@ -0,0 +1,410 @@
+//! A shout store in a single SQLite file, for LOUDBOTs living on a small host where
+//! running a redis just to hold a set of strings is overkill.
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use rusqlite::{params, Connection, OptionalExtension};
+
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use super::{ShoutStore, SWEEP_EVERY};
+use crate::now;
+
+/// One table per kind of key, plus a table of when keys expire.
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS sets (key TEXT NOT NULL, member TEXT NOT NULL, PRIMARY KEY (key, member));
+CREATE TABLE IF NOT EXISTS vals (key TEXT NOT NULL PRIMARY KEY, value TEXT NOT NULL);
+CREATE TABLE IF NOT EXISTS weights (key TEXT NOT NULL, member TEXT NOT NULL, weight REAL NOT NULL, PRIMARY KEY (key, member));
+CREATE TABLE IF NOT EXISTS logs (id INTEGER PRIMARY KEY AUTOINCREMENT, key TEXT NOT NULL, entry TEXT NOT NULL);
+CREATE INDEX IF NOT EXISTS logs_by_key ON logs (key, id);
+CREATE TABLE IF NOT EXISTS expiries (key TEXT NOT NULL PRIMARY KEY, at INTEGER NOT NULL);
+CREATE INDEX IF NOT EXISTS expiries_by_time ON expiries (at);
+";
+
+/// Every table that holds keys.
+const TABLES: &[&str] = &["sets", "vals", "weights", "logs", "expiries"];
+
+fn delete(db: &Connection, key: &str) -> Result<()> {
+    for table in TABLES {
+        db.execute(&format!("DELETE FROM {table} WHERE key = ?1"), [key])?;
+    }
+    Ok(())
+}
+
+/// Delete every key whose time is up.
+fn sweep(db: &Connection) -> Result<()> {
+    let now = now().as_millis() as i64;
+    for table in TABLES.iter().filter(|table| **table != "expiries") {
+        db.execute(
+            &format!("DELETE FROM {table} WHERE key IN (SELECT key FROM expiries WHERE at <= ?1)"),
+            [now],
+        )?;
+    }
+    db.execute("DELETE FROM expiries WHERE at <= ?1", [now])?;
+    Ok(())
+}
+
+fn set_expiry(db: &Connection, key: &str, ttl: Option<Duration>) -> Result<()> {
+    match ttl {
+        Some(ttl) => db.execute(
+            "INSERT OR REPLACE INTO expiries (key, at) VALUES (?1, ?2)",
+            params![key, (now() + ttl).as_millis() as i64],
+        )?,
+        None => db.execute("DELETE FROM expiries WHERE key = ?1", [key])?,
+    };
+    Ok(())
+}
+
+/// The connection, and how long it's been since we swept it.
+struct Database {
+    db: Connection,
+    operations: u64,
+}
+
+/// A shout store backed by a SQLite database file.
+pub struct SqliteStore {
+    database: Arc<Mutex<Database>>,
+}
+
+impl SqliteStore {
+    /// Open the database at this path, creating it and its tables if need be. The path
+    /// `:memory:` gives a database that vanishes when the store does.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let db = Connection::open(path).with_context(|| format!("Unable to open sqlite database @ {:?}", path))?;
+        db.execute_batch(SCHEMA)
+            .with_context(|| format!("Unable to create tables in {:?}", path))?;
+        Ok(SqliteStore {
+            database: Arc::new(Mutex::new(Database { db, operations: 0 })),
+        })
+    }
+
+    /// Do some work with the database on a blocking thread, so waiting on the lock or the
+    /// disk doesn't hold up an async worker. The given key is tidied away first if it's
+    /// expired, and every expired key if it's been a while.
+    async fn recall<T, F>(&self, key: Option<&str>, work: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Connection) -> Result<T> + Send + 'static,
+    {
+        let database = self.database.clone();
+        let key = key.map(|key| key.to_string());
+        tokio::task::spawn_blocking(move || {
+            let mut database = database.lock().map_err(|_| anyhow!("sqlite store lock poisoned"))?;
+            database.operations += 1;
+            if database.operations >= SWEEP_EVERY {
+                sweep(&database.db)?;
+                database.operations = 0;
+            } else if let Some(key) = &key {
+                let expired: bool = database.db.query_row(
+                    "SELECT EXISTS (SELECT 1 FROM expiries WHERE key = ?1 AND at <= ?2)",
+                    params![key, now().as_millis() as i64],
+                    |row| row.get(0),
+                )?;
+                if expired {
+                    delete(&database.db, key)?;
+                }
+            }
+            work(&database.db)
+        })
+        .await?
+    }
+}
+
+#[async_trait]
+impl ShoutStore for SqliteStore {
+    async fn remember(&self, set: &str, member: &str) -> Result<bool> {
+        let (key, member) = (set.to_string(), member.to_string());
+        self.recall(Some(set), move |db| {
+            let added = db.execute(
+                "INSERT OR IGNORE INTO sets (key, member) VALUES (?1, ?2)",
+                [&key, &member],
+            )?;
+            Ok(added > 0)
+        })
+        .await
+    }
+
+    async fn select(&self, set: &str) -> Result<Option<String>> {
+        let key = set.to_string();
+        self.recall(Some(set), move |db| {
+            Ok(db
+                .query_row(
+                    "SELECT member FROM sets WHERE key = ?1 ORDER BY RANDOM() LIMIT 1",
+                    [&key],
+                    |row| row.get(0),
+                )
+                .optional()?)
+        })
+        .await
+    }
+
+    async fn forget(&self, set: &str, member: &str) -> Result<bool> {
+        let (key, member) = (set.to_string(), member.to_string());
+        self.recall(Some(set), move |db| {
+            let removed = db.execute("DELETE FROM sets WHERE key = ?1 AND member = ?2", [&key, &member])?;
+            Ok(removed > 0)
+        })
+        .await
+    }
+
+    async fn cardinality(&self, set: &str) -> Result<u64> {
+        let key = set.to_string();
+        self.recall(Some(set), move |db| {
+            Ok(db.query_row("SELECT COUNT(*) FROM sets WHERE key = ?1", [&key], |row| row.get(0))?)
+        })
+        .await
+    }
+
+    async fn contains(&self, set: &str, member: &str) -> Result<bool> {
+        let (key, member) = (set.to_string(), member.to_string());
+        self.recall(Some(set), move |db| {
+            Ok(db.query_row(
+                "SELECT EXISTS (SELECT 1 FROM sets WHERE key = ?1 AND member = ?2)",
+                [&key, &member],
+                |row| row.get(0),
+            )?)
+        })
+        .await
+    }
+
+    async fn increment(&self, counter: &str) -> Result<u64> {
+        let key = counter.to_string();
+        self.recall(Some(counter), move |db| {
+            let count: String = db.query_row(
+                "INSERT INTO vals (key, value) VALUES (?1, '1')
+                 ON CONFLICT (key) DO UPDATE SET value = CAST(value AS INTEGER) + 1
+                 RETURNING value",
+                [&key],
+                |row| row.get(0),
+            )?;
+            count.parse().map_err(|_| anyhow!("{key} does not hold a number"))
+        })
+        .await
+    }
+
+    async fn count(&self, counter: &str) -> Result<Option<u64>> {
+        match self.get(counter).await? {
+            Some(v) => Ok(Some(
+                v.parse().map_err(|_| anyhow!("{counter} does not hold a number"))?,
+            )),
+            None => Ok(None),
+        }
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<String>> {
+        let owned = key.to_string();
+        self.recall(Some(key), move |db| {
+            Ok(db
+                .query_row("SELECT value FROM vals WHERE key = ?1", [&owned], |row| row.get(0))
+                .optional()?)
+        })
+        .await
+    }
+
+    async fn take(&self, key: &str) -> Result<Option<String>> {
+        let owned = key.to_string();
+        self.recall(Some(key), move |db| {
+            let value: Option<String> = db
+                .query_row("DELETE FROM vals WHERE key = ?1 RETURNING value", [&owned], |row| {
+                    row.get(0)
+                })
+                .optional()?;
+            if value.is_some() {
+                db.execute("DELETE FROM expiries WHERE key = ?1", [&owned])?;
+            }
+            Ok(value)
+        })
+        .await
+    }
+
+    async fn set(&self, key: &str, value: &str, ttl: Option<Duration>) -> Result<()> {
+        let (owned, value) = (key.to_string(), value.to_string());
+        self.recall(Some(key), move |db| {
+            db.execute(
+                "INSERT OR REPLACE INTO vals (key, value) VALUES (?1, ?2)",
+                [&owned, &value],
+            )?;
+            set_expiry(db, &owned, ttl)
+        })
+        .await
+    }
+
+    async fn set_if_absent(&self, key: &str, value: &str, ttl: Option<Duration>) -> Result<bool> {
+        let (owned, value) = (key.to_string(), value.to_string());
+        self.recall(Some(key), move |db| {
+            let added = db.execute(
+                "INSERT OR IGNORE INTO vals (key, value) VALUES (?1, ?2)",
+                [&owned, &value],
+            )?;
+            if added == 0 {
+                return Ok(false);
+            }
+            set_expiry(db, &owned, ttl)?;
+            Ok(true)
+        })
+        .await
+    }
+
+    async fn expire(&self, key: &str, ttl: Duration) -> Result<()> {
+        let owned = key.to_string();
+        self.recall(Some(key), move |db| {
+            let exists: bool = db.query_row(
+                "SELECT EXISTS (SELECT 1 FROM sets WHERE key = ?1
+                    UNION ALL SELECT 1 FROM vals WHERE key = ?1
+                    UNION ALL SELECT 1 FROM weights WHERE key = ?1
+                    UNION ALL SELECT 1 FROM logs WHERE key = ?1)",
+                [&owned],
+                |row| row.get(0),
+            )?;
+            if exists {
+                set_expiry(db, &owned, Some(ttl))?;
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    async fn delete(&self, keys: &[String]) -> Result<()> {
+        let keys = keys.to_vec();
+        self.recall(None, move |db| {
+            for key in &keys {
+                delete(db, key)?;
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    async fn weigh(&self, set: &str, member: &str, by: f64) -> Result<f64> {
+        let (key, member) = (set.to_string(), member.to_string());
+        self.recall(Some(set), move |db| {
+            Ok(db.query_row(
+                "INSERT INTO weights (key, member, weight) VALUES (?1, ?2, ?3)
+                 ON CONFLICT (key, member) DO UPDATE SET weight = weight + excluded.weight
+                 RETURNING weight",
+                params![key, member, by],
+                |row| row.get(0),
+            )?)
+        })
+        .await
+    }
+
+    async fn unweigh(&self, set: &str, member: &str) -> Result<()> {
+        let (key, member) = (set.to_string(), member.to_string());
+        self.recall(Some(set), move |db| {
+            db.execute("DELETE FROM weights WHERE key = ?1 AND member = ?2", [&key, &member])?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn weights(&self, set: &str) -> Result<Vec<(String, f64)>> {
+        let key = set.to_string();
+        self.recall(Some(set), move |db| {
+            let mut query = db.prepare("SELECT member, weight FROM weights WHERE key = ?1 ORDER BY weight, member")?;
+            let rows = query.query_map([&key], |row| Ok((row.get(0)?, row.get(1)?)))?;
+            Ok(rows.collect::<rusqlite::Result<Vec<(String, f64)>>>()?)
+        })
+        .await
+    }
+
+    async fn log(&self, key: &str, entry: &str, limit: usize) -> Result<()> {
+        let (owned, entry) = (key.to_string(), entry.to_string());
+        // SQLite reads a negative limit as no limit at all.
+        let limit = if limit == 0 { -1 } else { limit as i64 };
+        self.recall(Some(key), move |db| {
+            db.execute("INSERT INTO logs (key, entry) VALUES (?1, ?2)", [&owned, &entry])?;
+            db.execute(
+                "DELETE FROM logs WHERE key = ?1 AND id NOT IN
+                    (SELECT id FROM logs WHERE key = ?1 ORDER BY id DESC LIMIT ?2)",
+                params![owned, limit],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::outlive_untouched_keys;
+
+    #[tokio::test]
+    async fn sets_behave_like_sets() {
+        let store = SqliteStore::open(":memory:").unwrap();
+        assert!(store.remember("SET", "HELLO").await.unwrap());
+        assert!(!store.remember("SET", "HELLO").await.unwrap());
+        assert!(store.remember("SET", "GOODBYE").await.unwrap());
+        assert_eq!(store.cardinality("SET").await.unwrap(), 2);
+
+        assert!(store.forget("SET", "HELLO").await.unwrap());
+        assert!(!store.forget("SET", "HELLO").await.unwrap());
+        assert_eq!(store.select("SET").await.unwrap().as_deref(), Some("GOODBYE"));
+        assert_eq!(store.select("NOTHING").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn counters_and_weights_add_up() {
+        let store = SqliteStore::open(":memory:").unwrap();
+        assert_eq!(store.count("COUNT").await.unwrap(), None);
+        assert_eq!(store.increment("COUNT").await.unwrap(), 1);
+        assert_eq!(store.increment("COUNT").await.unwrap(), 2);
+        assert_eq!(store.count("COUNT").await.unwrap(), Some(2));
+
+        store.weigh("FAVES", "B", 1.0).await.unwrap();
+        assert_eq!(store.weigh("FAVES", "B", 2.0).await.unwrap(), 3.0);
+        store.weigh("FAVES", "A", 1.0).await.unwrap();
+        assert_eq!(
+            store.weights("FAVES").await.unwrap(),
+            vec![("A".to_string(), 1.0), ("B".to_string(), 3.0)]
+        );
+    }
+
+    #[tokio::test]
+    async fn values_expire_and_logs_are_capped() {
+        let store = SqliteStore::open(":memory:").unwrap();
+        store.set("BRIEF", "1", Some(Duration::from_millis(1))).await.unwrap();
+        std::thread::sleep(Duration::from_millis(5));
+        assert_eq!(store.get("BRIEF").await.unwrap(), None);
+        assert!(store.set_if_absent("BRIEF", "2", None).await.unwrap());
+        assert!(!store.set_if_absent("BRIEF", "3", None).await.unwrap());
+        assert_eq!(store.take("BRIEF").await.unwrap().as_deref(), Some("2"));
+        assert_eq!(store.take("BRIEF").await.unwrap(), None);
+
+        for entry in ["one", "two", "three"] {
+            store.log("LOG", entry, 2).await.unwrap();
+            store.log("WHOLE_LOG", entry, 0).await.unwrap();
+        }
+        let database = store.database.lock().unwrap();
+        let kept = |key: &str| -> Vec<String> {
+            database
+                .db
+                .prepare("SELECT entry FROM logs WHERE key = ?1 ORDER BY id")
+                .unwrap()
+                .query_map([key], |row| row.get(0))
+                .unwrap()
+                .collect::<rusqlite::Result<_>>()
+                .unwrap()
+        };
+        assert_eq!(kept("LOG"), vec!["two".to_string(), "three".to_string()]);
+        assert_eq!(kept("WHOLE_LOG").len(), 3);
+    }
+
+    #[tokio::test]
+    async fn untouched_keys_expire_too() {
+        let store = SqliteStore::open(":memory:").unwrap();
+        outlive_untouched_keys(&store).await;
+        let database = store.database.lock().unwrap();
+        let rows = |table: &str| -> i64 {
+            database
+                .db
+                .query_row(&format!("SELECT COUNT(*) FROM {table}"), [], |row| row.get(0))
+                .unwrap()
+        };
+        assert_eq!(rows("vals"), 1);
+        assert_eq!(rows("expiries"), 0);
+    }
+}
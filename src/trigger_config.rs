@@ -0,0 +1,177 @@
+//! EASTER EGGS TO ORDER. Triggers are defined in a TOML file: a name, a pattern, a chance,
+//! a reply mode, and somewhere to find responses. The built-in triggers are defined the
+//! same way, in `data/TRIGGERS.toml`, which is compiled in along with their corpora.
+use anyhow::{anyhow, Context, Result};
+use regex::Regex;
+use serde::Deserialize;
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::triggers::{ReplyMode, Trigger, MALCOLM_SET};
+
+/// The triggers every LOUDBOT ships with.
+const DEFAULT_TRIGGERS: &str = include_str!("data/TRIGGERS.toml");
+
+/// Response sets compiled into LOUDBOT, by the name a trigger file uses for them.
+const BUILTINS: &[(&str, &str)] = &[
+    ("CATS", include_str!("data/CATS")),
+    ("MALCOLM", include_str!("data/MALCOLM")),
+    ("SHIPS", include_str!("data/SHIPS")),
+    ("STAR_FIGHTING", include_str!("data/STAR_FIGHTING")),
+    ("STRATEGIES", include_str!("data/STRATEGIES")),
+];
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TriggerFile {
+    #[serde(default, rename = "trigger")]
+    triggers: Vec<TriggerDefinition>,
+}
+
+/// One trigger, as written in a trigger file.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TriggerDefinition {
+    name: String,
+    pattern: String,
+    chance: Option<u8>,
+    #[serde(default)]
+    mode: ReplyMode,
+    /// How many seconds the trigger keeps quiet in a channel after firing there.
+    cooldown: Option<u64>,
+    /// Responses listed right in the file.
+    responses: Option<Vec<String>>,
+    /// A newline-delimited file of responses, relative to the trigger file.
+    file: Option<PathBuf>,
+    /// One of the response sets compiled into LOUDBOT.
+    builtin: Option<String>,
+}
+
+impl TriggerDefinition {
+    fn build(self, dir: Option<&Path>, malc_chance: u8) -> Result<Trigger> {
+        let pattern = Regex::new(&self.pattern).context("pattern is not a valid regex")?;
+        let chance = match (self.chance, self.name == MALCOLM_SET) {
+            (Some(chance), _) if chance > 100 => return Err(anyhow!("chance {chance} is more than 100")),
+            (Some(chance), _) => chance,
+            (None, true) => malc_chance,
+            (None, false) => 100,
+        };
+        let responses = match (self.responses, self.file, self.builtin) {
+            (Some(responses), None, None) => responses,
+            (None, Some(file), None) => {
+                let file = match dir {
+                    Some(dir) => dir.join(file),
+                    None => file,
+                };
+                let text = std::fs::read_to_string(&file).with_context(|| format!("unable to read {:?}", file))?;
+                lines(&text)
+            }
+            (None, None, Some(builtin)) => match BUILTINS.iter().find(|(name, _)| *name == builtin) {
+                Some((_, text)) => lines(text),
+                None => return Err(anyhow!("there is no builtin response set called {builtin}")),
+            },
+            _ => return Err(anyhow!("needs exactly one of responses, file, or builtin")),
+        };
+        if responses.is_empty() {
+            return Err(anyhow!("has no responses"));
+        }
+        let trigger = Trigger::new(&self.name, pattern, responses, chance).with_mode(self.mode);
+        Ok(match self.cooldown {
+            Some(seconds) => trigger.with_cooldown(Duration::from_secs(seconds)),
+            None => trigger,
+        })
+    }
+}
+
+/// The non-empty lines of a response file.
+fn lines(text: &str) -> Vec<String> {
+    text.lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty())
+        .map(|line| line.to_string())
+        .collect()
+}
+
+/// Read triggers from the text of a trigger file. Files named in it are found relative
+/// to `dir`.
+pub fn parse_triggers(text: &str, dir: Option<&Path>, malc_chance: u8) -> Result<Vec<Trigger>> {
+    let file: TriggerFile = toml::from_str(text).context("not a valid trigger file")?;
+    let mut seen = HashSet::new();
+    let mut triggers = Vec::with_capacity(file.triggers.len());
+    for definition in file.triggers {
+        let name = definition.name.clone();
+        if !seen.insert(name.clone()) {
+            return Err(anyhow!("trigger {name} is defined more than once"));
+        }
+        triggers.push(
+            definition
+                .build(dir, malc_chance)
+                .with_context(|| format!("trigger {name}"))?,
+        );
+    }
+    Ok(triggers)
+}
+
+/// Read triggers from the trigger file at this path.
+pub fn load_triggers(path: impl AsRef<Path>, malc_chance: u8) -> Result<Vec<Trigger>> {
+    let path = path.as_ref();
+    let text = std::fs::read_to_string(path).with_context(|| format!("unable to read trigger file {:?}", path))?;
+    parse_triggers(&text, path.parent(), malc_chance).with_context(|| format!("in trigger file {:?}", path))
+}
+
+/// The built-in triggers.
+pub fn default_triggers(malc_chance: u8) -> Vec<Trigger> {
+    parse_triggers(DEFAULT_TRIGGERS, None, malc_chance).expect("the built-in trigger file is valid")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dice::LoadedDice;
+    use crate::origin::Origin;
+    use crate::triggers::{Draw, Responder, Retort, SW};
+
+    #[test]
+    fn the_defaults_are_the_classics() {
+        let triggers = default_triggers(2);
+        let sets: Vec<&str> = triggers.iter().map(|t| t.set()).collect();
+        assert_eq!(sets, vec!["CATS", "STARS", "SHIPS", "STRATEGIES", "MALC"]);
+        assert_eq!(triggers[1].pattern(), SW);
+        assert!(matches!(
+            triggers[0].respond("cat fact", &Origin::default(), None, &mut Draw::new(&LoadedDice(1))),
+            Some(Retort::Trigger { .. })
+        ));
+        assert!(default_triggers(0)[4]
+            .respond("fuck", &Origin::default(), None, &mut Draw::new(&LoadedDice(1)))
+            .is_none());
+    }
+
+    #[test]
+    fn bad_triggers_are_explained() {
+        let error = |text: &str| format!("{:#}", parse_triggers(text, None, 2).unwrap_err());
+
+        let bad_regex = "[[trigger]]\nname = 'CRABS'\npattern = '(CRAB'\nresponses = ['PINCH']";
+        assert!(error(bad_regex).starts_with("trigger CRABS: pattern is not a valid regex"));
+        let two_sources = "[[trigger]]\nname = 'CRABS'\npattern = 'CRAB'\nresponses = ['PINCH']\nbuiltin = 'CATS'";
+        assert!(error(two_sources).contains("exactly one of"));
+        let no_builtin = "[[trigger]]\nname = 'CRABS'\npattern = 'CRAB'\nbuiltin = 'LOBSTERS'";
+        assert!(error(no_builtin).contains("LOBSTERS"));
+        let too_likely = "[[trigger]]\nname = 'CRABS'\npattern = 'CRAB'\nchance = 200\nresponses = ['PINCH']";
+        assert!(error(too_likely).contains("more than 100"));
+        let typo = "[[trigger]]\nname = 'CRABS'\npatern = 'CRAB'\nresponses = ['PINCH']";
+        assert!(error(typo).contains("not a valid trigger file"));
+        let twice = "[[trigger]]\nname = 'A'\npattern = 'A'\nresponses = ['A']\n\n[[trigger]]\nname = 'A'\npattern = 'B'\nresponses = ['B']";
+        assert!(error(twice).contains("more than once"));
+    }
+
+    #[test]
+    fn triggers_can_react() {
+        let text = "[[trigger]]\nname = 'CRABS'\npattern = '(?i)CRAB'\nmode = 'reaction'\ncooldown = 60\nresponses = [':crab:']";
+        let triggers = parse_triggers(text, None, 2).unwrap();
+        let reaction = triggers[0].respond("crab rave", &Origin::default(), None, &mut Draw::new(&LoadedDice(1)));
+        assert!(matches!(reaction, Some(Retort::React(emoji)) if emoji == "crab"));
+        assert_eq!(Responder::cooldown(&triggers[0]), Some(Duration::from_secs(60)));
+    }
+}
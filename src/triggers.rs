@@ -1,21 +1,17 @@
-use rand::distributions::Uniform;
-use rand::prelude::*;
-use regex::Regex;
+use regex::{Captures, Regex};
+use serde::{Deserialize, Serialize};
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::dice::Dice;
+use crate::origin::Origin;
 
 /// Characters to strip out before considering the loudness of the input. This pattern depends on the order of the chunks.
 pub const IGNORE: &str = r":\w+:|<@\w+>|[\W\d[[:punct:]]]|s+";
 /// The famous movie quote trigger pattern, extracted for testing.
 pub const SW: &str = r"\b(?i)(LUKE +SKYWALKER|LEIA|SKYWALKER|ORGANA|TARKIN|LIGHTSABER|MILLENIUM +FALCON|DARTH +VADER|VADER|HAN +SOLO|OBIWAN|OBI-WAN|KENOBI|JABBA|CHEWIE|CHEWBACCA|TATOOINE|STAR +WARS?|DEATH +STAR|ALDERAAN|YAVIN|ENDOR)\b";
 
-/// Roll a mythical d100.
-fn roll_the_dice() -> u8 {
-    let rng = thread_rng();
-    let die_range = Uniform::new_inclusive(1, 100);
-    let mut dice = die_range.sample_iter(rng);
-
-    dice.next().unwrap_or(0)
-}
-
 /// Message retort types.
 #[derive(Clone, Debug)]
 pub enum Retort {
@@ -23,12 +19,195 @@ pub enum Retort {
     None,
     /// Select a random item from this category and then remember the input.
     Remember(String),
-    /// Retort with a self-report
-    Report,
     /// This is a built-in trigger; it includes the response.
     Trigger { retort: String, set: String },
     /// Retort with a preset response.
     Canned(String),
+    /// React to the input with this emoji instead of saying anything.
+    React(String),
+}
+
+/// The set Malcolm Tucker's responses are counted under. Channels can change how likely
+/// he is to appear.
+pub const MALCOLM_SET: &str = "MALC";
+
+/// Anything LOUDBOT asks about a message before treating it as a plain shout. Responders
+/// are asked in order, and the first with something to say wins.
+pub trait Responder: Send + Sync {
+    /// The set this responder's answers are counted under.
+    fn set(&self) -> &str;
+
+    /// Maybe answer this message from `origin`. `chance` overrides the responder's own
+    /// chance, if a channel has one for its set. Anything left to chance goes through `draw`.
+    fn respond(&self, text: &str, origin: &Origin, chance: Option<u8>, draw: &mut Draw) -> Option<Retort>;
+
+    /// How long this responder keeps quiet in a channel after answering there.
+    fn cooldown(&self) -> Option<Duration> {
+        None
+    }
+}
+
+/// The responses one set has left to deal in a channel before it starts over.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Bag {
+    /// Indexes of the responses left, dealt from the end.
+    left: Vec<usize>,
+    /// The response dealt most recently, so a new round doesn't start with it.
+    last: Option<usize>,
+}
+
+impl Bag {
+    pub fn is_empty(&self) -> bool {
+        self.left.is_empty() && self.last.is_none()
+    }
+}
+
+/// What the responders in one channel remember between messages: which sets are cooling
+/// down, and what each set has left in its bag. Kept in the store, so restarts don't
+/// start everything over.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TriggerState {
+    /// When each set may answer again, in epoch seconds.
+    cooling: HashMap<String, u64>,
+    bags: HashMap<String, Bag>,
+}
+
+impl TriggerState {
+    /// Whether this set is keeping quiet at `now`, in epoch seconds.
+    pub fn is_cooling(&self, set: &str, now: u64) -> bool {
+        matches!(self.cooling.get(set), Some(until) if *until > now)
+    }
+
+    /// Keep this set quiet until `until`, in epoch seconds.
+    pub fn cool(&mut self, set: &str, until: u64) {
+        self.cooling.insert(set.to_string(), until);
+    }
+
+    /// Forget cooldowns that are over.
+    pub fn tidy(&mut self, now: u64) {
+        self.cooling.retain(|_, until| *until > now);
+    }
+
+    /// Take this set's bag out to deal from. Put it back with `replace_bag`.
+    pub fn take_bag(&mut self, set: &str) -> Bag {
+        self.bags.remove(set).unwrap_or_default()
+    }
+
+    pub fn replace_bag(&mut self, set: &str, bag: Bag) {
+        if !bag.is_empty() {
+            self.bags.insert(set.to_string(), bag);
+        }
+    }
+}
+
+/// How a responder leaves things to chance while answering one message: dice to roll, and
+/// maybe a bag to deal responses from so they don't repeat until they've all had a turn.
+pub struct Draw<'a> {
+    dice: &'a dyn Dice,
+    bag: Option<&'a mut Bag>,
+}
+
+impl<'a> Draw<'a> {
+    /// Pick responses at random, repeats and all.
+    pub fn new(dice: &'a dyn Dice) -> Self {
+        Draw { dice, bag: None }
+    }
+
+    /// Deal responses from this bag, refilling it when it runs out.
+    pub fn from_bag(dice: &'a dyn Dice, bag: &'a mut Bag) -> Self {
+        Draw { dice, bag: Some(bag) }
+    }
+
+    pub fn dice(&self) -> &dyn Dice {
+        self.dice
+    }
+
+    /// One of these responses: the next in the bag if we have one, or any of them.
+    pub fn deal<'b, T>(&mut self, items: &'b [T]) -> Option<&'b T> {
+        let bag = match &mut self.bag {
+            Some(bag) => bag,
+            None => return self.dice.choose(items),
+        };
+        // The responses might have been reloaded since the bag was filled.
+        bag.left.retain(|i| *i < items.len());
+        if bag.left.is_empty() {
+            let mut fresh: Vec<usize> = (0..items.len()).collect();
+            for i in (1..fresh.len()).rev() {
+                fresh.swap(i, self.dice.below(i as u64 + 1) as usize);
+            }
+            // Dealing is from the end, so don't put the last response of the old round there.
+            if fresh.len() > 1 && fresh.last() == bag.last.as_ref() {
+                let end = fresh.len() - 1;
+                fresh.swap(0, end);
+            }
+            bag.left = fresh;
+        }
+        let index = bag.left.pop()?;
+        bag.last = Some(index);
+        items.get(index)
+    }
+}
+
+/// How a trigger answers when it fires.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReplyMode {
+    /// Post a line from the trigger's data.
+    #[default]
+    Message,
+    /// React with an emoji from the trigger's data.
+    Reaction,
+}
+
+/// Fill in a response's placeholders: `{1}` and so on, or `{name}` for a named group, with
+/// what the pattern captured, and `{user}` and `{channel}` with where the message came
+/// from. What we fill in is uppercased, because LOUDBOT. Placeholders we know nothing
+/// about are left as they are.
+fn fill(template: &str, pattern: &Regex, captures: &Captures, origin: &Origin) -> String {
+    let mut filled = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        filled.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let name = match rest.find('}') {
+            Some(end) => &rest[1..end],
+            None => break,
+        };
+        let value = match name {
+            "user" => Some(match &origin.user {
+                Some(user) => format!("<@{user}>"),
+                None => "SOMEBODY".to_string(),
+            }),
+            "channel" => Some(match &origin.channel {
+                Some(channel) => format!("<#{channel}>"),
+                None => "THIS CHANNEL".to_string(),
+            }),
+            _ => {
+                let group = match name.parse::<usize>() {
+                    Ok(n) if n < captures.len() => Some(captures.get(n)),
+                    Ok(_) => None,
+                    Err(_) if pattern.capture_names().any(|n| n == Some(name)) => Some(captures.name(name)),
+                    Err(_) => None,
+                };
+                // A group that didn't take part in the match is there, but empty.
+                group.map(|g| g.map(|g| g.as_str().to_uppercase()).unwrap_or_default())
+            }
+        };
+        match value {
+            Some(value) => {
+                filled.push_str(&value);
+                rest = &rest[name.len() + 2..];
+            }
+            None => {
+                filled.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    filled.push_str(rest);
+    filled
 }
 
 /// An easter egg trigger. These random response sets are built into LOUDBOT.
@@ -40,6 +219,8 @@ pub struct Trigger {
     pattern: Regex,
     data: Vec<String>,
     chance: u8,
+    mode: ReplyMode,
+    cooldown: Option<Duration>,
 }
 
 impl Trigger {
@@ -47,18 +228,9 @@ impl Trigger {
         &self.set
     }
 
-    pub fn maybe_respond(&self, text: &str) -> Option<Retort> {
-        if self.chance == 0 || roll_the_dice() > self.chance {
-            return None;
-        }
-        if !self.pattern.is_match(text) {
-            return None;
-        }
-
-        self.data.choose(&mut rand::thread_rng()).map(|retort| Retort::Trigger {
-            retort: retort.to_string(),
-            set: self.set().to_string(),
-        })
+    /// The pattern that sets this trigger off.
+    pub fn pattern(&self) -> &str {
+        self.pattern.as_str()
     }
 
     pub fn new(base: &str, pattern: Regex, data: Vec<String>, chance: u8) -> Self {
@@ -68,15 +240,52 @@ impl Trigger {
             pattern,
             data,
             chance,
+            mode: ReplyMode::default(),
+            cooldown: None,
+        }
+    }
+
+    /// Answer with this reply mode instead of the default, which is to post a message.
+    pub fn with_mode(mut self, mode: ReplyMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Keep quiet in a channel for this long after firing there.
+    pub fn with_cooldown(mut self, cooldown: Duration) -> Self {
+        self.cooldown = Some(cooldown);
+        self
+    }
+}
+
+impl Responder for Trigger {
+    fn set(&self) -> &str {
+        &self.set
+    }
+
+    fn respond(&self, text: &str, origin: &Origin, chance: Option<u8>, draw: &mut Draw) -> Option<Retort> {
+        if !draw.dice().chance(chance.unwrap_or(self.chance)) {
+            return None;
+        }
+        let captures = self.pattern.captures(text)?;
+
+        let choice = draw.deal(&self.data)?;
+        match self.mode {
+            ReplyMode::Message => Some(Retort::Trigger {
+                retort: fill(choice, &self.pattern, &captures, origin),
+                set: self.set().to_string(),
+            }),
+            ReplyMode::Reaction => Some(Retort::React(choice.trim_matches(':').to_string())),
         }
     }
+
+    fn cooldown(&self) -> Option<Duration> {
+        self.cooldown
+    }
 }
 
-// Note refactoring opportunity: this has the same API surface as the other triggers
-// but takes a little more configuration. Also, the implementation of matches is different.
-// I'd like to pull this into a trait when I can figure out how to store these in a vector
-// somewhere. A vec of Box<dyn Trigger> is not sized, however, so I can't store them on the
-// loudbot struct as that needs to go into a an arc. Maybe a once_cell static? They aren't mutable.
+/// Malcolm Tucker, summoned by name or by saying goodbye the way he does. He answers
+/// with a gif rather than anything from a response set.
 #[derive(Debug, Clone)]
 pub struct MalcolmSpecials {
     chance: u8,
@@ -89,28 +298,30 @@ impl MalcolmSpecials {
     pub fn new(chance: u8) -> Self {
         Self {
             chance,
-            set: "MALC".to_string(),
+            set: MALCOLM_SET.to_string(),
             fuckity: Regex::new("(?i)FUCKITY.?BYE").unwrap(),
             summon: Regex::new("(?i)MALCOLM +TUCKER +MALCOLM +TUCKER").unwrap(),
         }
     }
+}
 
+impl Responder for MalcolmSpecials {
     fn set(&self) -> &str {
         &self.set
     }
 
-    pub fn maybe_respond(&self, text: &str) -> Option<Retort> {
-        if self.chance == 0 || roll_the_dice() > self.chance {
+    fn respond(&self, text: &str, _origin: &Origin, chance: Option<u8>, draw: &mut Draw) -> Option<Retort> {
+        if !draw.dice().chance(chance.unwrap_or(self.chance)) {
             None
         } else if self.fuckity.is_match(text) {
             Some(Retort::Trigger {
                 retort: "https://cldup.com/NtvUeudPtg.gif".to_string(),
-                set: self.set().to_string(),
+                set: self.set.clone(),
             })
         } else if self.summon.is_match(text) {
             Some(Retort::Trigger {
                 retort: "https://cldup.com/w_exMqXKlT.gif".to_string(),
-                set: self.set().to_string(),
+                set: self.set.clone(),
             })
         } else {
             None
@@ -121,6 +332,7 @@ impl MalcolmSpecials {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::dice::LoadedDice;
 
     #[test]
     fn movie_easter_egg_works() {
@@ -135,4 +347,107 @@ mod tests {
         assert!(!patt.is_match("vendor"));
         assert!(patt.is_match("third moon of Endor"));
     }
+
+    #[test]
+    fn reaction_triggers_react() {
+        let trigger = Trigger::new(
+            "CRABS",
+            Regex::new("(?i)CRAB").unwrap(),
+            vec![":crab:".to_string()],
+            100,
+        )
+        .with_mode(ReplyMode::Reaction);
+        let nobody = Origin::default();
+        match trigger.respond("crab rave", &nobody, None, &mut Draw::new(&LoadedDice(1))) {
+            Some(Retort::React(emoji)) => assert_eq!(emoji, "crab"),
+            _ => unreachable!("we should have reacted with a crab"),
+        }
+        assert!(trigger
+            .respond("lobster rave", &nobody, None, &mut Draw::new(&LoadedDice(1)))
+            .is_none());
+    }
+
+    #[test]
+    fn responses_fill_in_what_matched() {
+        let trigger = Trigger::new(
+            "HATE",
+            Regex::new(r"(?i)I HATE (\w+)(?: ON (?P<day>\w+))?").unwrap(),
+            vec!["{1} HATES YOU TOO, {user}. NOT IN {channel} ON {day}. {3} {x}".to_string()],
+            100,
+        );
+        let origin = Origin {
+            user: Some("U1".to_string()),
+            channel: Some("C1".to_string()),
+            ..Origin::default()
+        };
+        match trigger.respond("i hate mondays", &origin, None, &mut Draw::new(&LoadedDice(1))) {
+            Some(Retort::Trigger { retort, .. }) => {
+                assert_eq!(retort, "MONDAYS HATES YOU TOO, <@U1>. NOT IN <#C1> ON . {3} {x}")
+            }
+            _ => unreachable!("we should have hated back"),
+        }
+        match trigger.respond(
+            "I hate cats on tuesday",
+            &Origin::default(),
+            None,
+            &mut Draw::new(&LoadedDice(1)),
+        ) {
+            Some(Retort::Trigger { retort, .. }) => {
+                assert_eq!(
+                    retort,
+                    "CATS HATES YOU TOO, SOMEBODY. NOT IN THIS CHANNEL ON TUESDAY. {3} {x}"
+                )
+            }
+            _ => unreachable!("we should have hated back"),
+        }
+    }
+
+    #[test]
+    fn bags_deal_everything_before_repeating() {
+        use crate::dice::SeededDice;
+        let items: Vec<usize> = (0..5).collect();
+        let dice = SeededDice::new(2014);
+        let mut bag = Bag::default();
+        let dealt: Vec<usize> = (0..50)
+            .map(|_| *Draw::from_bag(&dice, &mut bag).deal(&items).unwrap())
+            .collect();
+        for round in dealt.chunks(items.len()) {
+            let mut round = round.to_vec();
+            round.sort_unstable();
+            assert_eq!(round, items);
+        }
+        assert!(dealt.windows(2).all(|pair| pair[0] != pair[1]));
+        // A bag filled for more responses than there are now still deals one of them.
+        assert!(matches!(Draw::from_bag(&dice, &mut bag).deal(&items[..1]), Some(0)));
+    }
+
+    #[test]
+    fn chances_in_between_are_honored() {
+        let trigger = Trigger::new(
+            "CRABS",
+            Regex::new("(?i)CRAB").unwrap(),
+            vec!["ONE".to_string(), "TWO".to_string()],
+            50,
+        );
+        let nobody = Origin::default();
+        match trigger.respond("crab rave", &nobody, None, &mut Draw::new(&LoadedDice(50))) {
+            Some(Retort::Trigger { retort, .. }) => assert_eq!(retort, "ONE"),
+            _ => unreachable!("a roll of 50 should make a 50% chance"),
+        }
+        assert!(trigger
+            .respond("crab rave", &nobody, None, &mut Draw::new(&LoadedDice(51)))
+            .is_none());
+        // A chance from the channel's settings overrides the trigger's own.
+        assert!(trigger
+            .respond("crab rave", &nobody, Some(60), &mut Draw::new(&LoadedDice(51)))
+            .is_some());
+
+        let malcolm = MalcolmSpecials::new(10);
+        assert!(malcolm
+            .respond("fuckity bye", &nobody, None, &mut Draw::new(&LoadedDice(10)))
+            .is_some());
+        assert!(malcolm
+            .respond("fuckity bye", &nobody, None, &mut Draw::new(&LoadedDice(11)))
+            .is_none());
+    }
 }